@@ -0,0 +1,163 @@
+#![no_main]
+
+// encode_module.rs - structured fuzzing of the translator -> encoder pipeline
+//
+// wasm-smith generates valid Wasm modules by construction rather than
+// mutating raw bytes; the same idea applies one layer up, to rv2wasm's own
+// IR. We generate a randomized `WasmModule` (varied block_addr spacing,
+// instruction mixes, block counts spanning the `br_table`/`call_indirect`
+// boundary) and run it through `wasm_builder::build`, asserting the
+// encoder never produces a module `wasmparser` rejects. This targets
+// exactly the spots where the byte-indexed dispatch map and nested-block
+// br_table are fragile: the empty-module and single-block cases, address
+// spacing wide enough to inflate `table_size`, non-contiguous
+// `block_addr` values, and the `n == 0` / `n >= 255` boundaries.
+
+use libfuzzer_sys::fuzz_target;
+use rv2wasm::translate::{WasmFunction, WasmInst, WasmModule};
+
+#[derive(Debug, arbitrary::Arbitrary)]
+struct FuzzFunction {
+    /// Spacing from the previous function's block_addr, in half-words;
+    /// kept small most of the time but occasionally large to stress
+    /// `table_size` growth.
+    addr_gap: u16,
+    insts: Vec<FuzzInst>,
+    num_locals: u8,
+}
+
+#[derive(Debug, arbitrary::Arbitrary)]
+enum FuzzInst {
+    I32Const(i32),
+    I64Const(i64),
+    I64Add,
+    I64Sub,
+    LocalGet(u8),
+    LocalSet(u8),
+    Return,
+}
+
+/// The value-type stack `typed_body` tracks while assembling a function, so
+/// it only ever emits instructions that are well-typed for a block function
+/// (`(param $m i32) (result i32)`, locals: local 0 is the i32 param, locals
+/// `1..=num_locals` are i64).
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    I32,
+    I64,
+}
+
+/// Turn an arbitrary `FuzzInst` stream into a body that `wasmparser` accepts.
+///
+/// `FuzzInst` values are generated with no notion of the Wasm type system, so
+/// most raw streams would push/pop the wrong types or leave the wrong thing
+/// (or nothing, or too much) on the stack at the implicit `end`. This walks
+/// the stream with its own little type stack, keeping an instruction only
+/// when its operands are actually there, then drains whatever is left on the
+/// stack and pushes a final `i32` so the function's declared result type is
+/// always satisfied. `num_locals` is assumed to be at least 1, so there's
+/// always an i64 local to drain i64 values into.
+fn typed_body(insts: Vec<FuzzInst>, num_locals: u32) -> Vec<WasmInst> {
+    let mut body = Vec::new();
+    let mut stack: Vec<Ty> = Vec::new();
+
+    for inst in insts {
+        match inst {
+            FuzzInst::I32Const(value) => {
+                body.push(WasmInst::I32Const { value });
+                stack.push(Ty::I32);
+            }
+            FuzzInst::I64Const(value) => {
+                body.push(WasmInst::I64Const { value });
+                stack.push(Ty::I64);
+            }
+            FuzzInst::I64Add | FuzzInst::I64Sub => {
+                let top_two_i64 = matches!(stack[..], [.., Ty::I64, Ty::I64]);
+                if top_two_i64 {
+                    stack.pop();
+                    body.push(if matches!(inst, FuzzInst::I64Add) { WasmInst::I64Add } else { WasmInst::I64Sub });
+                }
+            }
+            FuzzInst::LocalGet(idx) => {
+                // Local 0 is the i32 param; locals 1..=num_locals are i64.
+                let idx = (idx as u32) % (num_locals + 1);
+                body.push(WasmInst::LocalGet { idx });
+                stack.push(if idx == 0 { Ty::I32 } else { Ty::I64 });
+            }
+            FuzzInst::LocalSet(idx) => {
+                let idx = (idx as u32) % (num_locals + 1);
+                let wants = if idx == 0 { Ty::I32 } else { Ty::I64 };
+                if stack.last() == Some(&wants) {
+                    stack.pop();
+                    body.push(WasmInst::LocalSet { idx });
+                }
+            }
+            FuzzInst::Return => {
+                // `return` only needs the function's result type (i32) on
+                // top; what's underneath doesn't matter, since the rest of
+                // the body becomes unreachable and the implicit `end` no
+                // longer constrains the stack. Stop assembling right here
+                // rather than simulating unreachable code.
+                if stack.last() == Some(&Ty::I32) {
+                    body.push(WasmInst::Return);
+                    return body;
+                }
+            }
+        }
+    }
+
+    // No (valid) `Return` seen: drain whatever is left so the implicit `end`
+    // sees exactly the declared result, one `i32`.
+    while let Some(ty) = stack.pop() {
+        body.push(WasmInst::LocalSet { idx: if ty == Ty::I32 { 0 } else { 1 } });
+    }
+    body.push(WasmInst::I32Const { value: 0 });
+    body
+}
+
+#[derive(Debug, arbitrary::Arbitrary)]
+struct FuzzModule {
+    functions: Vec<FuzzFunction>,
+    memory_pages: u8,
+}
+
+fuzz_target!(|input: FuzzModule| {
+    // Cap the function count so the fuzzer can still explore the
+    // br_table/call_indirect boundary (n == 0, n == 254, n == 255) without
+    // every run paying for thousands of functions.
+    let mut addr = 0x1000u64;
+    let functions: Vec<WasmFunction> = input
+        .functions
+        .into_iter()
+        .take(600)
+        .map(|f| {
+            addr += 2 + (f.addr_gap as u64) * 2;
+            // At least one i64 local, so `typed_body` always has somewhere
+            // to drain a leftover i64 value when it fixes up the stack.
+            let num_locals = (f.num_locals % 8) as u32 + 1;
+            WasmFunction {
+                name: format!("block_{addr:x}"),
+                block_addr: addr,
+                num_locals,
+                body: typed_body(f.insts.into_iter().take(64).collect(), num_locals),
+            }
+        })
+        .collect();
+
+    let module = WasmModule {
+        functions,
+        memory_pages: (input.memory_pages as u32).max(1),
+        fuel_metering: false,
+    };
+
+    let Ok(bytes) = rv2wasm::wasm_builder::build(&module, None) else {
+        // A translation error for a malformed fuzz input is acceptable;
+        // an invalid-but-produced module is not.
+        return;
+    };
+
+    let mut validator = wasmparser::Validator::new();
+    if let Err(e) = validator.validate_all(&bytes) {
+        panic!("encoder produced invalid Wasm for a structurally valid WasmModule: {e}");
+    }
+});
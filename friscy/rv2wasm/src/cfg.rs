@@ -0,0 +1,432 @@
+// cfg.rs - Basic-block and function-boundary construction
+//
+// Bridges `disasm::disassemble`'s flat instruction stream to the
+// block/function-shaped graph the rest of the pipeline needs: a block ends
+// at a control-flow instruction (or at the start of the next block), and a
+// function begins wherever a `JAL` targets with `rd == 1` (the standard
+// call-return-address convention) land, plus the program's entry point.
+//
+// This is the concrete CFG builder `relooper` and `reachability` were
+// written against but never had a real caller for: `Cfg::structured_shape`
+// hands a function's blocks to `relooper::reloop`, and
+// `Cfg::prune_unreachable` hands the function list to `reachability::prune`.
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{bail, Result};
+
+use crate::disasm::{Instruction, Kind};
+use crate::reachability::{self, FunctionNode, ReachabilityReport};
+use crate::relooper::{self, RelooperBlock, Shape};
+
+/// One basic block: a contiguous run of instructions with a single entry
+/// point, ending at a control-flow instruction (or at the start of another
+/// block, or the end of the section).
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: u64,
+    pub instructions: Vec<Instruction>,
+    pub successors: Vec<u64>,
+}
+
+/// The program's control-flow graph: every basic block plus the function
+/// boundaries found among them.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    pub functions: Vec<FunctionNode>,
+    pub entry: u64,
+}
+
+/// Build a [`Cfg`] from a flat instruction stream, as produced by
+/// `disasm::disassemble` (instructions from multiple code sections can be
+/// concatenated first; block/function boundaries are derived purely from
+/// each instruction's own `addr`/`kind`).
+pub fn build(instructions: &[Instruction], entry: u64) -> Result<Cfg> {
+    if instructions.is_empty() {
+        bail!("cfg::build: no instructions to build a graph from");
+    }
+
+    let blocks = split_into_blocks(instructions);
+    let block_starts: BTreeSet<u64> = blocks.iter().map(|b| b.start).collect();
+
+    let mut function_starts: BTreeSet<u64> = BTreeSet::new();
+    function_starts.insert(entry);
+    for inst in instructions {
+        if let Kind::Jal { rd: 1, offset } = inst.kind {
+            function_starts.insert((inst.addr as i64 + offset as i64) as u64);
+        }
+    }
+    // A call can target an address that isn't actually a block we decoded
+    // (e.g. a PLT stub outside the section we were handed); only addresses
+    // that line up with a real block become a `FunctionNode`.
+    function_starts.retain(|addr| block_starts.contains(addr));
+
+    let functions = function_starts
+        .iter()
+        .map(|&addr| FunctionNode {
+            addr,
+            direct_targets: direct_call_targets(instructions, addr, &function_starts),
+            address_taken: false,
+        })
+        .collect();
+
+    Ok(Cfg { blocks, functions, entry })
+}
+
+/// Split a flat instruction stream into basic blocks. A new block starts at
+/// the first instruction, at every branch/jump target, and at the
+/// fall-through of every branch/jump/`ecall`.
+fn split_into_blocks(instructions: &[Instruction]) -> Vec<Block> {
+    let addr_index: HashMap<u64, usize> = instructions.iter().enumerate().map(|(i, ins)| (ins.addr, i)).collect();
+
+    let mut starts: BTreeSet<u64> = BTreeSet::new();
+    starts.insert(instructions[0].addr);
+    for inst in instructions {
+        let fallthrough = inst.addr + inst.len as u64;
+        match inst.kind {
+            Kind::Beq { offset, .. } | Kind::Bne { offset, .. } | Kind::Jal { offset, .. } => {
+                starts.insert((inst.addr as i64 + offset as i64) as u64);
+                starts.insert(fallthrough);
+            }
+            Kind::Jalr { .. } | Kind::Ecall => {
+                starts.insert(fallthrough);
+            }
+            _ => {}
+        }
+    }
+    starts.retain(|addr| addr_index.contains_key(addr));
+
+    let starts: Vec<u64> = starts.into_iter().collect();
+    let mut blocks = Vec::with_capacity(starts.len());
+    for (i, &start) in starts.iter().enumerate() {
+        let start_idx = addr_index[&start];
+        let end_idx = starts.get(i + 1).map_or(instructions.len(), |next| addr_index[next]);
+        let body = instructions[start_idx..end_idx].to_vec();
+
+        let last = body.last().expect("a block always has at least its start instruction");
+        let fallthrough = last.addr + last.len as u64;
+        let successors = match last.kind {
+            Kind::Beq { offset, .. } | Kind::Bne { offset, .. } => {
+                vec![(last.addr as i64 + offset as i64) as u64, fallthrough]
+            }
+            Kind::Jal { offset, .. } => vec![(last.addr as i64 + offset as i64) as u64],
+            Kind::Jalr { .. } | Kind::Ecall => vec![],
+            _ if addr_index.contains_key(&fallthrough) => vec![fallthrough],
+            _ => vec![],
+        };
+
+        blocks.push(Block { start, instructions: body, successors });
+    }
+    blocks
+}
+
+/// The `JAL rd=1` call targets made by the function starting at
+/// `func_start`, up to (but not including) the next known function start.
+fn direct_call_targets(instructions: &[Instruction], func_start: u64, function_starts: &BTreeSet<u64>) -> Vec<u64> {
+    let func_end = function_starts.range((func_start + 1)..).next().copied();
+    instructions
+        .iter()
+        .filter(|inst| inst.addr >= func_start && func_end.is_none_or(|end| inst.addr < end))
+        .filter_map(|inst| match inst.kind {
+            Kind::Jal { rd: 1, offset } => Some((inst.addr as i64 + offset as i64) as u64),
+            _ => None,
+        })
+        .collect()
+}
+
+impl Cfg {
+    /// The block graph in the shape `relooper::reloop` expects, with edges
+    /// into any of `other_entries` cut.
+    ///
+    /// A `JAL rd=1` call site's successor points straight at the callee,
+    /// same as a plain jump would (`split_into_blocks` doesn't distinguish
+    /// them), so without this cut the relooper would fold the callee's
+    /// entire body into the caller's shape — and then fold it in *again*
+    /// when asked for the callee's own shape, since the callee is also a
+    /// `FunctionNode` in its own right. Cutting these edges loses nothing
+    /// `translate` needs: a call has no fallthrough edge in this graph
+    /// either (the return-address instruction becomes its own untargeted
+    /// block, handled as an orphan), so codegen always resumes a call via
+    /// the host dispatch loop regardless.
+    fn relooper_blocks_for_function(&self, other_entries: &BTreeSet<u64>) -> HashMap<u64, RelooperBlock> {
+        self.blocks
+            .iter()
+            .map(|b| {
+                let successors = b.successors.iter().copied().filter(|s| !other_entries.contains(s)).collect();
+                (b.start, RelooperBlock { id: b.start, successors })
+            })
+            .collect()
+    }
+
+    /// Derive the structured `block`/`loop`/`if` shape for the function
+    /// starting at `func_entry`, for codegen to lower instead of routing
+    /// every block transition through the flat `br_table` trampoline.
+    ///
+    /// `relooper::is_irreducible` isn't useful here: a function has exactly
+    /// one entry, and `is_irreducible` only ever flags a region given two or
+    /// more entries to compare against each other, so it would always say
+    /// "reducible" regardless of what the function's blocks actually look
+    /// like. That's fine in practice — `relooper::reloop` is total; any
+    /// merge it can't express as nested `Simple`/`Loop` still comes out as a
+    /// `Multiple`, dispatched on `$__label` rather than true structured
+    /// nesting. So this always succeeds.
+    pub fn structured_shape(&self, func_entry: u64) -> Shape {
+        let other_entries: BTreeSet<u64> = self.functions.iter().map(|f| f.addr).filter(|&a| a != func_entry).collect();
+        let blocks = self.relooper_blocks_for_function(&other_entries);
+        relooper::reloop(&blocks, &[func_entry])
+    }
+
+    /// Scan `data` for pointer-width little-endian constants that equal one
+    /// of this CFG's function addresses, and mark those functions'
+    /// `address_taken` so `prune_unreachable` keeps them even without a
+    /// direct call edge (function-pointer tables, `atexit`/`.init_array`
+    /// entries, vtables — anything that reaches a function through a value
+    /// loaded from memory rather than a `JAL`). `xlen` (32 or 64, from
+    /// `elf::ElfInfo::xlen`) selects the pointer width to scan for.
+    ///
+    /// This is a heuristic, not real relocation parsing (`elf::parse`
+    /// doesn't decode `.rela.dyn`/`.rela.plt`): a function address that
+    /// merely happens to collide with unrelated data is indistinguishable
+    /// from a real function pointer here, so this errs toward keeping too
+    /// much rather than silently dropping a function that's only ever
+    /// reached indirectly. Callers should pass the non-executable loadable
+    /// segments (the ones `extract_code_sections` skips), not the whole
+    /// file, to keep false positives down.
+    pub fn mark_address_taken(&mut self, data: &[u8], xlen: u32) {
+        let word_size = if xlen == 32 { 4 } else { 8 };
+        if self.functions.is_empty() || data.len() < word_size {
+            return;
+        }
+        let addrs: BTreeSet<u64> = self.functions.iter().map(|f| f.addr).collect();
+
+        let mut taken: BTreeSet<u64> = BTreeSet::new();
+        for window in data.windows(word_size) {
+            let value = if word_size == 4 {
+                u32::from_le_bytes(window.try_into().unwrap()) as u64
+            } else {
+                u64::from_le_bytes(window.try_into().unwrap())
+            };
+            // A zero word is overwhelmingly padding/uninitialized data, not
+            // a real function pointer, and treating it as one would mark
+            // address_taken on a function at vaddr 0 for essentially any
+            // zero-filled data segment.
+            if value != 0 && addrs.contains(&value) {
+                taken.insert(value);
+            }
+        }
+
+        for f in &mut self.functions {
+            if taken.contains(&f.addr) {
+                f.address_taken = true;
+            }
+        }
+    }
+
+    /// Drop functions unreachable from `entry` (plus `extra_roots`, e.g.
+    /// `.init_array` entries or relocation-named symbols a future caller
+    /// resolves), along with the basic blocks that belonged only to them.
+    /// Dynamically linked binaries pull in a lot of libc that the
+    /// translated program never calls; this is what keeps the emitted
+    /// dispatch table and code section down to just what's reachable.
+    pub fn prune_unreachable(self, extra_roots: &[u64]) -> (Cfg, ReachabilityReport) {
+        let Cfg { blocks, functions, entry } = self;
+        let (kept_functions, report) = reachability::prune(functions, entry, extra_roots);
+
+        let kept_starts: BTreeSet<u64> = kept_functions.iter().map(|f| f.addr).collect();
+        let kept_blocks = prune_blocks_to_functions(blocks, &kept_starts);
+
+        (Cfg { blocks: kept_blocks, functions: kept_functions, entry }, report)
+    }
+}
+
+/// Keep only the blocks reachable (by straight-line fallthrough or branch,
+/// without crossing into another function's entry) from one of
+/// `kept_function_starts`.
+fn prune_blocks_to_functions(blocks: Vec<Block>, kept_function_starts: &BTreeSet<u64>) -> Vec<Block> {
+    let by_start: HashMap<u64, &Block> = blocks.iter().map(|b| (b.start, b)).collect();
+
+    let mut keep: BTreeSet<u64> = BTreeSet::new();
+    let mut stack: Vec<u64> = kept_function_starts.iter().copied().collect();
+    while let Some(addr) = stack.pop() {
+        if !keep.insert(addr) {
+            continue;
+        }
+        if let Some(block) = by_start.get(&addr) {
+            for &succ in &block.successors {
+                // Stop at another function's entry: it's only reachable
+                // through a call, which is exactly the edge `reachability`
+                // already decided on via `direct_targets`, not a fallthrough
+                // edge this block-level walk should follow. A function's
+                // own entry is already seeded on the stack, so skipping it
+                // here just avoids redundant work, not correctness.
+                if !kept_function_starts.contains(&succ) {
+                    stack.push(succ);
+                }
+            }
+        }
+    }
+
+    blocks.into_iter().filter(|b| keep.contains(&b.start)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beq(addr: u64, offset: i32) -> Instruction {
+        Instruction { addr, len: 4, kind: Kind::Beq { rs1: 0, rs2: 0, offset } }
+    }
+    fn jal(addr: u64, rd: u8, offset: i32) -> Instruction {
+        Instruction { addr, len: 4, kind: Kind::Jal { rd, offset } }
+    }
+    fn addi(addr: u64) -> Instruction {
+        Instruction { addr, len: 4, kind: Kind::Addi { rd: 1, rs1: 1, imm: 1 } }
+    }
+
+    #[test]
+    fn straight_line_code_is_a_single_block() {
+        let instrs = vec![addi(0), addi(4), addi(8)];
+        let cfg = build(&instrs, 0).unwrap();
+
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.blocks[0].start, 0);
+        assert_eq!(cfg.blocks[0].instructions.len(), 3);
+    }
+
+    #[test]
+    fn a_branch_splits_the_stream_into_three_blocks() {
+        // 0: beq -> 12 (taken) / 4 (fallthrough); 4: addi; 8: addi (unreachable
+        // padding so the fallthrough block isn't empty); 12: addi (target).
+        let instrs = vec![beq(0, 12), addi(4), addi(8), addi(12)];
+        let cfg = build(&instrs, 0).unwrap();
+        let starts: BTreeSet<u64> = cfg.blocks.iter().map(|b| b.start).collect();
+
+        assert_eq!(starts, BTreeSet::from([0, 4, 12]));
+        let entry_block = cfg.blocks.iter().find(|b| b.start == 0).unwrap();
+        assert_eq!(BTreeSet::from_iter(entry_block.successors.iter().copied()), BTreeSet::from([4, 12]));
+    }
+
+    #[test]
+    fn a_jal_with_rd_1_introduces_a_function_boundary() {
+        // 0: jal ra, +8 (call); 4: addi (never reached by straight-line fall
+        // through, since jal doesn't fall through); 8: addi (callee entry).
+        let instrs = vec![jal(0, 1, 8), addi(4), addi(8)];
+        let cfg = build(&instrs, 0).unwrap();
+
+        let addrs: BTreeSet<u64> = cfg.functions.iter().map(|f| f.addr).collect();
+        assert_eq!(addrs, BTreeSet::from([0, 8]));
+        assert_eq!(cfg.functions.iter().find(|f| f.addr == 0).unwrap().direct_targets, vec![8]);
+    }
+
+    #[test]
+    fn structured_shape_does_not_absorb_a_callees_blocks() {
+        // 0: jal ra, +8 (call to function at 8); 4: addi (the return site,
+        // an orphan block with no incoming edge in this graph); 8: addi
+        // (the callee, its own FunctionNode).
+        let instrs = vec![jal(0, 1, 8), addi(4), addi(8)];
+        let cfg = build(&instrs, 0).unwrap();
+
+        match cfg.structured_shape(0) {
+            Shape::Simple { id: 0, next, .. } => assert!(matches!(*next, Shape::None)),
+            other => panic!("expected a lone Simple shape for the call site, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn structured_shape_builds_for_straight_line_functions() {
+        let instrs = vec![addi(0), addi(4), addi(8)];
+        let cfg = build(&instrs, 0).unwrap();
+
+        match cfg.structured_shape(0) {
+            Shape::Simple { id: 0, .. } => {}
+            other => panic!("expected a Simple shape rooted at 0, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn build_rejects_an_empty_instruction_stream() {
+        assert!(build(&[], 0).is_err());
+    }
+
+    #[test]
+    fn prune_unreachable_drops_an_uncalled_function_and_its_blocks() {
+        // Entry 0 calls function 8; function 12 exists (e.g. reached only
+        // via an indirect call we can't resolve) but nothing in this graph
+        // ever calls it, so it and its block should be dropped.
+        let cfg = Cfg {
+            blocks: vec![
+                Block { start: 0, instructions: vec![jal(0, 1, 8)], successors: vec![8] },
+                Block { start: 8, instructions: vec![addi(8)], successors: vec![] },
+                Block { start: 12, instructions: vec![addi(12)], successors: vec![] },
+            ],
+            functions: vec![
+                FunctionNode { addr: 0, direct_targets: vec![8], address_taken: false },
+                FunctionNode { addr: 8, direct_targets: vec![], address_taken: false },
+                FunctionNode { addr: 12, direct_targets: vec![], address_taken: false },
+            ],
+            entry: 0,
+        };
+
+        let (pruned, report) = cfg.prune_unreachable(&[]);
+
+        assert_eq!(report.total, 3);
+        assert_eq!(report.kept, BTreeSet::from([0, 8]).into_iter().collect());
+        assert_eq!(
+            pruned.functions.iter().map(|f| f.addr).collect::<BTreeSet<_>>(),
+            BTreeSet::from([0, 8])
+        );
+        assert!(
+            pruned.blocks.iter().all(|b| b.start != 12),
+            "the unreachable function's block should have been dropped too"
+        );
+    }
+
+    #[test]
+    fn mark_address_taken_sets_the_flag_for_a_matching_pointer_constant() {
+        let instrs = vec![jal(0, 1, 8), addi(4), addi(8)];
+        let mut cfg = build(&instrs, 0).unwrap();
+        assert!(cfg.functions.iter().all(|f| !f.address_taken));
+
+        // A function-pointer table: two 8-byte little-endian entries, one
+        // of which (8) is a real function address and one (0xDEAD) isn't.
+        let mut data = Vec::new();
+        data.extend_from_slice(&8u64.to_le_bytes());
+        data.extend_from_slice(&0xDEADu64.to_le_bytes());
+        cfg.mark_address_taken(&data, 64);
+
+        assert!(cfg.functions.iter().find(|f| f.addr == 8).unwrap().address_taken);
+        assert!(!cfg.functions.iter().find(|f| f.addr == 0).unwrap().address_taken);
+    }
+
+    #[test]
+    fn mark_address_taken_respects_xlen_32_pointer_width() {
+        let instrs = vec![jal(0, 1, 8), addi(4), addi(8)];
+        let mut cfg = build(&instrs, 0).unwrap();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&8u32.to_le_bytes());
+        cfg.mark_address_taken(&data, 32);
+
+        assert!(cfg.functions.iter().find(|f| f.addr == 8).unwrap().address_taken);
+    }
+
+    #[test]
+    fn mark_address_taken_is_a_noop_without_any_matching_bytes() {
+        let instrs = vec![addi(0), addi(4)];
+        let mut cfg = build(&instrs, 0).unwrap();
+        cfg.mark_address_taken(&[0u8; 32], 64);
+        assert!(cfg.functions.iter().all(|f| !f.address_taken));
+    }
+
+    #[test]
+    fn prune_unreachable_keeps_extra_roots() {
+        let instrs = vec![addi(0), addi(4)];
+        let mut cfg = build(&instrs, 0).unwrap();
+        cfg.functions.push(FunctionNode { addr: 100, direct_targets: vec![], address_taken: false });
+
+        let (pruned, report) = cfg.prune_unreachable(&[100]);
+        assert_eq!(report.kept, BTreeSet::from([0, 100]).into_iter().collect());
+        assert!(pruned.functions.iter().any(|f| f.addr == 100));
+    }
+}
@@ -0,0 +1,429 @@
+// elf.rs - ELF32/ELF64 parsing for RISC-V binaries
+//
+// Detects `EI_CLASS` and parses either the 32- or 64-bit ELF header and
+// program header table, then upcasts every address/size field to `u64` so
+// the rest of the pipeline (disasm, cfg, codegen) never has to branch on
+// word size again. An `xlen` marker records which class the binary was so
+// codegen can still choose 32- vs 64-bit register/arithmetic semantics.
+
+use anyhow::{bail, Context, Result};
+
+const EI_CLASS: usize = 4;
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+
+const ET_DYN: u16 = 3;
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+
+/// A loadable (or otherwise interesting) ELF program header, with every
+/// field widened to `u64` regardless of the source ELF class.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+/// Parsed ELF metadata, uniform across 32- and 64-bit inputs.
+#[derive(Debug, Clone)]
+pub struct ElfInfo {
+    /// 32 or 64, taken from `EI_CLASS`.
+    pub xlen: u32,
+    pub entry: u64,
+    pub is_pie: bool,
+    pub interpreter: Option<String>,
+    pub segments: Vec<Segment>,
+    pub phdr_vaddr: u64,
+    pub phdr_count: u64,
+    pub phdr_entsize: u64,
+}
+
+/// Parse an ELF32 or ELF64 RISC-V binary, detecting the class from
+/// `EI_CLASS` and returning a class-independent [`ElfInfo`].
+pub fn parse(data: &[u8]) -> Result<ElfInfo> {
+    if data.len() < 20 || &data[0..4] != b"\x7fELF" {
+        bail!("not an ELF file");
+    }
+
+    match data[EI_CLASS] {
+        ELFCLASS32 => parse_class::<u32>(data),
+        ELFCLASS64 => parse_class::<u64>(data),
+        class => bail!("unsupported EI_CLASS: {class}"),
+    }
+}
+
+/// Width-specific field accessors, implemented for `u32` (ELF32) and `u64`
+/// (ELF64) so `parse_class` can be written once and upcast at the edges.
+trait Word: Copy {
+    const SIZE: usize;
+    fn read(data: &[u8], offset: usize) -> Result<Self>;
+    fn widen(self) -> u64;
+}
+
+impl Word for u32 {
+    const SIZE: usize = 4;
+    fn read(data: &[u8], offset: usize) -> Result<Self> {
+        read_u32(data, offset)
+    }
+    fn widen(self) -> u64 {
+        self as u64
+    }
+}
+
+impl Word for u64 {
+    const SIZE: usize = 8;
+    fn read(data: &[u8], offset: usize) -> Result<Self> {
+        read_u64(data, offset)
+    }
+    fn widen(self) -> u64 {
+        self
+    }
+}
+
+/// Bounds-checked little-endian field readers, used instead of raw slice
+/// indexing everywhere a header/program-header field is pulled out of
+/// attacker-controlled input, so a truncated file returns `Err` instead of
+/// panicking.
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    let bytes = data
+        .get(offset..offset + 2)
+        .with_context(|| format!("truncated ELF field at offset {offset} (u16)"))?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = data
+        .get(offset..offset + 4)
+        .with_context(|| format!("truncated ELF field at offset {offset} (u32)"))?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64> {
+    let bytes = data
+        .get(offset..offset + 8)
+        .with_context(|| format!("truncated ELF field at offset {offset} (u64)"))?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Checked `[offset, offset+len)` as a `usize` range, for turning an
+/// attacker-controlled `(offset, size)` header pair into slice bounds
+/// without the addition itself overflowing before `data.get(..)` ever
+/// gets a chance to reject it.
+fn checked_range(offset: u64, len: u64) -> Result<std::ops::Range<usize>> {
+    let end = offset.checked_add(len).context("offset+size overflow")?;
+    let start: usize = offset.try_into().context("offset+size overflow")?;
+    let end: usize = end.try_into().context("offset+size overflow")?;
+    Ok(start..end)
+}
+
+fn parse_class<W: Word>(data: &[u8]) -> Result<ElfInfo> {
+    let xlen = if W::SIZE == 4 { 32 } else { 64 };
+
+    // Header layout differs after e_type/e_machine/e_version because
+    // ELF32 uses Elf32_Addr/Off (4 bytes) where ELF64 uses 8.
+    let e_type = read_u16(data, 16)?;
+    let entry_off = 24;
+    let entry = W::read(data, entry_off)?.widen();
+
+    let phoff_off = entry_off + W::SIZE;
+    let phoff = W::read(data, phoff_off)?.widen();
+
+    // e_phentsize/e_phnum sit after e_shoff, e_flags, e_ehsize; their
+    // offsets from the start of the header are fixed per class.
+    let (phentsize_off, phnum_off) = if xlen == 32 {
+        (42, 44)
+    } else {
+        (54, 56)
+    };
+    let phentsize = read_u16(data, phentsize_off)? as u64;
+    let phnum = read_u16(data, phnum_off)? as u64;
+
+    let mut segments = Vec::with_capacity(phnum as usize);
+    let mut interpreter = None;
+    let mut phdr_vaddr = 0u64;
+
+    for i in 0..phnum {
+        let base = phoff
+            .checked_add(i.checked_mul(phentsize).context("program header table offset overflow")?)
+            .context("program header table offset overflow")?;
+        let range = checked_range(base, phentsize).context("program header table offset overflow")?;
+        let ph = data
+            .get(range)
+            .with_context(|| format!("truncated program header {i}"))?;
+
+        let (p_type, flags, offset, vaddr, filesz, memsz, align) = if xlen == 32 {
+            let p_type = read_u32(ph, 0).with_context(|| format!("truncated program header {i} (p_type)"))?;
+            let offset = read_u32(ph, 4).with_context(|| format!("truncated program header {i} (p_offset)"))? as u64;
+            let vaddr = read_u32(ph, 8).with_context(|| format!("truncated program header {i} (p_vaddr)"))? as u64;
+            let filesz = read_u32(ph, 16).with_context(|| format!("truncated program header {i} (p_filesz)"))? as u64;
+            let memsz = read_u32(ph, 20).with_context(|| format!("truncated program header {i} (p_memsz)"))? as u64;
+            let flags = read_u32(ph, 24).with_context(|| format!("truncated program header {i} (p_flags)"))?;
+            let align = read_u32(ph, 28).with_context(|| format!("truncated program header {i} (p_align)"))? as u64;
+            (p_type, flags, offset, vaddr, filesz, memsz, align)
+        } else {
+            let p_type = read_u32(ph, 0).with_context(|| format!("truncated program header {i} (p_type)"))?;
+            let flags = read_u32(ph, 4).with_context(|| format!("truncated program header {i} (p_flags)"))?;
+            let offset = read_u64(ph, 8).with_context(|| format!("truncated program header {i} (p_offset)"))?;
+            let vaddr = read_u64(ph, 16).with_context(|| format!("truncated program header {i} (p_vaddr)"))?;
+            let filesz = read_u64(ph, 32).with_context(|| format!("truncated program header {i} (p_filesz)"))?;
+            let memsz = read_u64(ph, 40).with_context(|| format!("truncated program header {i} (p_memsz)"))?;
+            let align = read_u64(ph, 48).with_context(|| format!("truncated program header {i} (p_align)"))?;
+            (p_type, flags, offset, vaddr, filesz, memsz, align)
+        };
+
+        if p_type == PT_INTERP {
+            if let Ok(range) = checked_range(offset, filesz) {
+                if let Some(bytes) = data.get(range) {
+                    let nul = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+                    interpreter = Some(String::from_utf8_lossy(&bytes[..nul]).into_owned());
+                }
+            }
+        }
+        if p_type == PT_PHDR {
+            phdr_vaddr = vaddr;
+        }
+
+        segments.push(Segment {
+            p_type,
+            flags,
+            offset,
+            vaddr,
+            filesz,
+            memsz,
+            align,
+        });
+    }
+
+    // Statically-linked binaries have no PT_PHDR; fall back to the
+    // e_phoff-relative address computed against whichever PT_LOAD segment's
+    // file range actually covers the program header table. Not every
+    // layout has one (the table can sit outside all loadable segments), in
+    // which case phdr_vaddr is left at 0 rather than guessed.
+    if phdr_vaddr == 0 {
+        if let Some(load) = segments
+            .iter()
+            .find(|s| s.p_type == PT_LOAD && phoff >= s.offset && phoff - s.offset < s.filesz)
+        {
+            phdr_vaddr = load.vaddr + (phoff - load.offset);
+        }
+    }
+
+    Ok(ElfInfo {
+        xlen,
+        entry,
+        is_pie: e_type == ET_DYN,
+        interpreter,
+        segments,
+        phdr_vaddr,
+        phdr_count: phnum,
+        phdr_entsize: phentsize,
+    })
+}
+
+/// Extract the bytes of each loadable, executable segment, for handing to
+/// `disasm::disassemble`.
+pub fn extract_code_sections<'a>(data: &'a [u8], info: &ElfInfo) -> Result<Vec<&'a [u8]>> {
+    const PF_X: u32 = 1;
+    let mut sections = Vec::new();
+    for seg in &info.segments {
+        if seg.p_type == PT_LOAD && seg.flags & PF_X != 0 {
+            let range = checked_range(seg.offset, seg.filesz).context("executable segment offset+size overflow")?;
+            sections.push(
+                data.get(range)
+                    .context("executable segment out of bounds")?,
+            );
+        }
+    }
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assemble a minimal ELF64 file: the 64-byte header plus one 56-byte
+    /// `PT_LOAD`+executable program header pointing at `code`, which is
+    /// appended immediately after the program header table.
+    fn minimal_elf64(e_type: u16, entry: u64, code: &[u8]) -> Vec<u8> {
+        const EHDR_SIZE: u64 = 64;
+        const PHDR_SIZE: u64 = 56;
+        let phoff = EHDR_SIZE;
+        let code_off = phoff + PHDR_SIZE;
+
+        let mut f = vec![0u8; (code_off + code.len() as u64) as usize];
+        f[0..4].copy_from_slice(b"\x7fELF");
+        f[EI_CLASS] = ELFCLASS64;
+        f[5] = 1; // ELFDATA2LSB
+        f[16..18].copy_from_slice(&e_type.to_le_bytes());
+        f[24..32].copy_from_slice(&entry.to_le_bytes()); // e_entry
+        f[32..40].copy_from_slice(&phoff.to_le_bytes()); // e_phoff
+        f[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes()); // e_phentsize
+        f[56..58].copy_from_slice(&1u16.to_le_bytes()); // e_phnum
+
+        let ph = phoff as usize;
+        f[ph..ph + 4].copy_from_slice(&PT_LOAD.to_le_bytes()); // p_type
+        f[ph + 4..ph + 8].copy_from_slice(&1u32.to_le_bytes()); // p_flags = PF_X
+        f[ph + 8..ph + 16].copy_from_slice(&code_off.to_le_bytes()); // p_offset
+        f[ph + 16..ph + 24].copy_from_slice(&0x10000u64.to_le_bytes()); // p_vaddr
+        f[ph + 32..ph + 40].copy_from_slice(&(code.len() as u64).to_le_bytes()); // p_filesz
+        f[ph + 40..ph + 48].copy_from_slice(&(code.len() as u64).to_le_bytes()); // p_memsz
+        f[ph + 48..ph + 56].copy_from_slice(&0x1000u64.to_le_bytes()); // p_align
+
+        f[code_off as usize..].copy_from_slice(code);
+        f
+    }
+
+    #[test]
+    fn parses_minimal_elf64_header_and_load_segment() {
+        let data = minimal_elf64(2 /* ET_EXEC */, 0x10000, &[0x93, 0x00, 0x50, 0x00]);
+        let info = parse(&data).unwrap();
+
+        assert_eq!(info.xlen, 64);
+        assert_eq!(info.entry, 0x10000);
+        assert!(!info.is_pie);
+        assert_eq!(info.segments.len(), 1);
+        assert_eq!(info.segments[0].vaddr, 0x10000);
+        assert_eq!(info.segments[0].filesz, 4);
+    }
+
+    #[test]
+    fn et_dyn_is_reported_as_pie() {
+        let data = minimal_elf64(3 /* ET_DYN */, 0, &[0u8; 4]);
+        assert!(parse(&data).unwrap().is_pie);
+    }
+
+    #[test]
+    fn extract_code_sections_returns_the_executable_segment_bytes() {
+        let code = [0x93, 0x00, 0x50, 0x00];
+        let data = minimal_elf64(2, 0x10000, &code);
+        let info = parse(&data).unwrap();
+        let sections = extract_code_sections(&data, &info).unwrap();
+
+        assert_eq!(sections, vec![&code[..]]);
+    }
+
+    #[test]
+    fn rejects_data_without_the_elf_magic() {
+        assert!(parse(&[0u8; 64]).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_ei_class() {
+        let mut data = minimal_elf64(2, 0x10000, &[0u8; 4]);
+        data[EI_CLASS] = 0;
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_elf64_header_truncated_before_phnum() {
+        // Magic and EI_CLASS are valid and data.len() >= 20, but the header
+        // is cut off before e_phentsize/e_phnum: parse_class must return
+        // `Err` instead of panicking on an out-of-bounds slice index.
+        let data = minimal_elf64(2, 0x10000, &[0u8; 4]);
+        let truncated = &data[..40];
+        assert!(parse(truncated).is_err());
+    }
+
+    #[test]
+    fn rejects_elf64_program_header_truncated_mid_entry() {
+        let data = minimal_elf64(2, 0x10000, &[0x93, 0x00, 0x50, 0x00]);
+        // Keep the 64-byte header (which claims one 56-byte program header)
+        // but cut the file off partway through that program header's
+        // trailing fields (p_memsz/p_align).
+        let truncated = &data[..64 + 40];
+        assert!(parse(truncated).is_err());
+    }
+
+    #[test]
+    fn rejects_elf64_phoff_near_u64_max_instead_of_overflowing() {
+        // A crafted e_phoff near u64::MAX must not panic on overflow when
+        // `phoff + i * phentsize` is computed (overflow-checks are on in
+        // debug/test builds); it should surface as a parse error instead.
+        let mut data = minimal_elf64(2, 0x10000, &[0x93, 0x00, 0x50, 0x00]);
+        data[32..40].copy_from_slice(&(u64::MAX - 4).to_le_bytes()); // e_phoff
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_elf64_p_filesz_near_u64_max_instead_of_overflowing() {
+        // A crafted p_offset/p_filesz near u64::MAX in extract_code_sections
+        // must not panic on `start + filesz` overflowing.
+        let data = minimal_elf64(2, 0x10000, &[0x93, 0x00, 0x50, 0x00]);
+        let mut info = parse(&data).unwrap();
+        info.segments[0].filesz = u64::MAX - 4;
+        assert!(extract_code_sections(&data, &info).is_err());
+    }
+
+    #[test]
+    fn rejects_elf32_header_truncated_before_phnum() {
+        let data = minimal_elf32(2, 0x10000, &[0u8; 4]);
+        let truncated = &data[..30];
+        assert!(parse(truncated).is_err());
+    }
+
+    /// Assemble a minimal ELF32 file: the 52-byte header plus one 32-byte
+    /// `PT_LOAD`+executable program header pointing at `code`. ELF32's
+    /// header and program-header field offsets diverge from ELF64's past
+    /// `e_type`/`e_machine`/`e_version` (4-byte `Elf32_Addr`/`Off` instead
+    /// of 8), which is exactly the byte arithmetic `parse_class` branches
+    /// on, so this needs its own fixture rather than reusing `minimal_elf64`.
+    fn minimal_elf32(e_type: u16, entry: u32, code: &[u8]) -> Vec<u8> {
+        const EHDR_SIZE: u32 = 52;
+        const PHDR_SIZE: u32 = 32;
+        let phoff = EHDR_SIZE;
+        let code_off = phoff + PHDR_SIZE;
+
+        let mut f = vec![0u8; (code_off + code.len() as u32) as usize];
+        f[0..4].copy_from_slice(b"\x7fELF");
+        f[EI_CLASS] = ELFCLASS32;
+        f[5] = 1; // ELFDATA2LSB
+        f[16..18].copy_from_slice(&e_type.to_le_bytes());
+        f[24..28].copy_from_slice(&entry.to_le_bytes()); // e_entry
+        f[28..32].copy_from_slice(&phoff.to_le_bytes()); // e_phoff
+        f[42..44].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes()); // e_phentsize
+        f[44..46].copy_from_slice(&1u16.to_le_bytes()); // e_phnum
+
+        let ph = phoff as usize;
+        f[ph..ph + 4].copy_from_slice(&PT_LOAD.to_le_bytes()); // p_type
+        f[ph + 4..ph + 8].copy_from_slice(&code_off.to_le_bytes()); // p_offset
+        f[ph + 8..ph + 12].copy_from_slice(&0x10000u32.to_le_bytes()); // p_vaddr
+        f[ph + 16..ph + 20].copy_from_slice(&(code.len() as u32).to_le_bytes()); // p_filesz
+        f[ph + 20..ph + 24].copy_from_slice(&(code.len() as u32).to_le_bytes()); // p_memsz
+        f[ph + 24..ph + 28].copy_from_slice(&1u32.to_le_bytes()); // p_flags = PF_X
+        f[ph + 28..ph + 32].copy_from_slice(&0x1000u32.to_le_bytes()); // p_align
+
+        f[code_off as usize..].copy_from_slice(code);
+        f
+    }
+
+    #[test]
+    fn parses_minimal_elf32_header_and_load_segment() {
+        let code = [0x93, 0x00, 0x50, 0x00];
+        let data = minimal_elf32(2 /* ET_EXEC */, 0x10000, &code);
+        let info = parse(&data).unwrap();
+
+        assert_eq!(info.xlen, 32);
+        assert_eq!(info.entry, 0x10000);
+        assert!(!info.is_pie);
+        assert_eq!(info.segments.len(), 1);
+        assert_eq!(info.segments[0].vaddr, 0x10000);
+        assert_eq!(info.segments[0].filesz, 4);
+        assert_eq!(info.segments[0].flags & 1, 1, "PF_X must survive the ELF32 field reorder");
+    }
+
+    #[test]
+    fn extract_code_sections_works_for_elf32_too() {
+        let code = [0x93, 0x00, 0x50, 0x00];
+        let data = minimal_elf32(2, 0x10000, &code);
+        let info = parse(&data).unwrap();
+        let sections = extract_code_sections(&data, &info).unwrap();
+
+        assert_eq!(sections, vec![&code[..]]);
+    }
+}
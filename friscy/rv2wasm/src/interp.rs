@@ -0,0 +1,344 @@
+// interp.rs - Reference RISC-V interpreter for differential testing
+//
+// A small, deliberately unoptimized interpreter over decoded RISC-V
+// instructions. It exists purely as an oracle: run a test program through
+// here, run the same program through the compiled Wasm module under an
+// embedded engine, and compare final architectural state. Any divergence
+// means `disasm`/codegen produced wrong (but possibly still
+// valid-looking) Wasm.
+
+use crate::disasm;
+use anyhow::{bail, Result};
+
+/// The subset of decoded instruction semantics the interpreter needs to
+/// execute a program. Mirrors the opcodes `disasm::disassemble` produces.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    Addi { rd: u8, rs1: u8, imm: i64 },
+    Add { rd: u8, rs1: u8, rs2: u8 },
+    Sub { rd: u8, rs1: u8, rs2: u8 },
+    Lw { rd: u8, rs1: u8, imm: i64 },
+    Ld { rd: u8, rs1: u8, imm: i64 },
+    Sw { rs1: u8, rs2: u8, imm: i64 },
+    Sd { rs1: u8, rs2: u8, imm: i64 },
+    Beq { rs1: u8, rs2: u8, target: u64 },
+    Bne { rs1: u8, rs2: u8, target: u64 },
+    Jal { rd: u8, target: u64 },
+    Jalr { rd: u8, rs1: u8, imm: i64 },
+    Ecall,
+    LrW { rd: u8, rs1: u8 },
+    LrD { rd: u8, rs1: u8 },
+    ScW { rd: u8, rs1: u8, rs2: u8 },
+    ScD { rd: u8, rs1: u8, rs2: u8 },
+    AmoAddW { rd: u8, rs1: u8, rs2: u8 },
+    AmoAddD { rd: u8, rs1: u8, rs2: u8 },
+    AmoSwapW { rd: u8, rs1: u8, rs2: u8 },
+    AmoSwapD { rd: u8, rs1: u8, rs2: u8 },
+}
+
+/// Lower `disasm::disassemble`'s output into the interpreter's own `Instr`
+/// stream, resolving branch/jump offsets (relative to the instruction's
+/// address) into absolute targets. This is the bridge `conformance_test.rs`
+/// uses to run the same decoded program through both the reference
+/// interpreter and the compiled Wasm module.
+///
+/// Any `disasm::Kind` without interpreter semantics fails loudly rather
+/// than being silently dropped or treated as a no-op, since either would
+/// make the interpreter a bad oracle.
+pub fn lower(instrs: &[disasm::Instruction]) -> Result<Vec<Instr>> {
+    instrs
+        .iter()
+        .map(|i| {
+            let op = match i.kind {
+                disasm::Kind::Addi { rd, rs1, imm } => Op::Addi { rd, rs1, imm: imm as i64 },
+                disasm::Kind::Add { rd, rs1, rs2 } => Op::Add { rd, rs1, rs2 },
+                disasm::Kind::Sub { rd, rs1, rs2 } => Op::Sub { rd, rs1, rs2 },
+                disasm::Kind::Lw { rd, rs1, imm } => Op::Lw { rd, rs1, imm: imm as i64 },
+                disasm::Kind::Ld { rd, rs1, imm } => Op::Ld { rd, rs1, imm: imm as i64 },
+                disasm::Kind::Sw { rs1, rs2, imm } => Op::Sw { rs1, rs2, imm: imm as i64 },
+                disasm::Kind::Sd { rs1, rs2, imm } => Op::Sd { rs1, rs2, imm: imm as i64 },
+                disasm::Kind::Beq { rs1, rs2, offset } => Op::Beq {
+                    rs1,
+                    rs2,
+                    target: (i.addr as i64 + offset as i64) as u64,
+                },
+                disasm::Kind::Bne { rs1, rs2, offset } => Op::Bne {
+                    rs1,
+                    rs2,
+                    target: (i.addr as i64 + offset as i64) as u64,
+                },
+                disasm::Kind::Jal { rd, offset } => Op::Jal {
+                    rd,
+                    target: (i.addr as i64 + offset as i64) as u64,
+                },
+                disasm::Kind::Jalr { rd, rs1, imm } => Op::Jalr { rd, rs1, imm: imm as i64 },
+                disasm::Kind::Ecall => Op::Ecall,
+                disasm::Kind::LrW { rd, rs1 } => Op::LrW { rd, rs1 },
+                disasm::Kind::LrD { rd, rs1 } => Op::LrD { rd, rs1 },
+                disasm::Kind::ScW { rd, rs1, rs2 } => Op::ScW { rd, rs1, rs2 },
+                disasm::Kind::ScD { rd, rs1, rs2 } => Op::ScD { rd, rs1, rs2 },
+                disasm::Kind::AmoAddW { rd, rs1, rs2 } => Op::AmoAddW { rd, rs1, rs2 },
+                disasm::Kind::AmoAddD { rd, rs1, rs2 } => Op::AmoAddD { rd, rs1, rs2 },
+                disasm::Kind::AmoSwapW { rd, rs1, rs2 } => Op::AmoSwapW { rd, rs1, rs2 },
+                disasm::Kind::AmoSwapD { rd, rs1, rs2 } => Op::AmoSwapD { rd, rs1, rs2 },
+                other => bail!("interp::lower: no interpreter semantics for {other:?} at pc=0x{:x}", i.addr),
+            };
+            Ok(Instr { pc: i.addr, len: i.len, op })
+        })
+        .collect()
+}
+
+/// One decoded instruction plus the guest PC it was fetched from and its
+/// encoded length (2 for RVC-compressed, 4 for standard), needed to
+/// advance `pc` to the real next instruction on non-branch ops.
+#[derive(Debug, Clone, Copy)]
+pub struct Instr {
+    pub pc: u64,
+    pub len: u8,
+    pub op: Op,
+}
+
+/// Interpreter outcome after running to completion or hitting the step
+/// budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Exited,
+    StepLimit,
+    Trap,
+}
+
+/// Architectural state the interpreter tracks: 32 integer registers, a
+/// byte-addressable memory image, and the program counter.
+pub struct Interp {
+    pub regs: [u64; 32],
+    pub pc: u64,
+    pub mem: Vec<u8>,
+    mem_base: u64,
+}
+
+impl Interp {
+    /// Create an interpreter whose memory image starts at `mem_base` and
+    /// is pre-populated with `image` (typically the concatenation of a
+    /// binary's loadable segments).
+    pub fn new(entry: u64, mem_base: u64, image: Vec<u8>) -> Self {
+        Interp {
+            regs: [0; 32],
+            pc: entry,
+            mem: image,
+            mem_base,
+        }
+    }
+
+    fn load_u64(&self, addr: u64) -> u64 {
+        let off = (addr - self.mem_base) as usize;
+        u64::from_le_bytes(self.mem[off..off + 8].try_into().unwrap())
+    }
+
+    fn load_u32(&self, addr: u64) -> u32 {
+        let off = (addr - self.mem_base) as usize;
+        u32::from_le_bytes(self.mem[off..off + 4].try_into().unwrap())
+    }
+
+    fn store_u64(&mut self, addr: u64, value: u64) {
+        let off = (addr - self.mem_base) as usize;
+        self.mem[off..off + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn store_u32(&mut self, addr: u64, value: u32) {
+        let off = (addr - self.mem_base) as usize;
+        self.mem[off..off + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_reg(&mut self, idx: u8, value: u64) {
+        if idx != 0 {
+            self.regs[idx as usize] = value;
+        }
+    }
+
+    /// Run `program` (assumed sorted by `pc`, one entry per instruction,
+    /// indexed by address via linear scan) for up to `max_steps`,
+    /// trapping on `ecall` or falling off the end.
+    pub fn run(&mut self, program: &[Instr], max_steps: u64) -> Result<StopReason> {
+        for _ in 0..max_steps {
+            let Some(instr) = program.iter().find(|i| i.pc == self.pc) else {
+                return Ok(StopReason::Exited);
+            };
+            let len = instr.len as u64;
+
+            match instr.op {
+                Op::Addi { rd, rs1, imm } => {
+                    let v = (self.regs[rs1 as usize] as i64).wrapping_add(imm) as u64;
+                    self.write_reg(rd, v);
+                    self.pc += len;
+                }
+                Op::Add { rd, rs1, rs2 } => {
+                    self.write_reg(rd, self.regs[rs1 as usize].wrapping_add(self.regs[rs2 as usize]));
+                    self.pc += len;
+                }
+                Op::Sub { rd, rs1, rs2 } => {
+                    self.write_reg(rd, self.regs[rs1 as usize].wrapping_sub(self.regs[rs2 as usize]));
+                    self.pc += len;
+                }
+                Op::Lw { rd, rs1, imm } => {
+                    let addr = (self.regs[rs1 as usize] as i64).wrapping_add(imm) as u64;
+                    self.write_reg(rd, self.load_u32(addr) as i32 as i64 as u64);
+                    self.pc += len;
+                }
+                Op::Ld { rd, rs1, imm } => {
+                    let addr = (self.regs[rs1 as usize] as i64).wrapping_add(imm) as u64;
+                    self.write_reg(rd, self.load_u64(addr));
+                    self.pc += len;
+                }
+                Op::Sw { rs1, rs2, imm } => {
+                    let addr = (self.regs[rs1 as usize] as i64).wrapping_add(imm) as u64;
+                    self.store_u32(addr, self.regs[rs2 as usize] as u32);
+                    self.pc += len;
+                }
+                Op::Sd { rs1, rs2, imm } => {
+                    let addr = (self.regs[rs1 as usize] as i64).wrapping_add(imm) as u64;
+                    self.store_u64(addr, self.regs[rs2 as usize]);
+                    self.pc += len;
+                }
+                Op::Beq { rs1, rs2, target } => {
+                    self.pc = if self.regs[rs1 as usize] == self.regs[rs2 as usize] {
+                        target
+                    } else {
+                        self.pc + len
+                    };
+                }
+                Op::Bne { rs1, rs2, target } => {
+                    self.pc = if self.regs[rs1 as usize] != self.regs[rs2 as usize] {
+                        target
+                    } else {
+                        self.pc + len
+                    };
+                }
+                Op::Jal { rd, target } => {
+                    self.write_reg(rd, self.pc + len);
+                    self.pc = target;
+                }
+                Op::Jalr { rd, rs1, imm } => {
+                    let next = ((self.regs[rs1 as usize] as i64).wrapping_add(imm) as u64) & !1;
+                    self.write_reg(rd, self.pc + len);
+                    self.pc = next;
+                }
+                Op::Ecall => return Ok(StopReason::Trap),
+                // A single-hart oracle: a plain load-then-store matches the
+                // real atomic's result, and `sc.*` always succeeds since
+                // there's no other hart to have broken the reservation.
+                Op::LrW { rd, rs1 } => {
+                    self.write_reg(rd, self.load_u32(self.regs[rs1 as usize]) as i32 as i64 as u64);
+                    self.pc += len;
+                }
+                Op::LrD { rd, rs1 } => {
+                    self.write_reg(rd, self.load_u64(self.regs[rs1 as usize]));
+                    self.pc += len;
+                }
+                Op::ScW { rd, rs1, rs2 } => {
+                    self.store_u32(self.regs[rs1 as usize], self.regs[rs2 as usize] as u32);
+                    self.write_reg(rd, 0);
+                    self.pc += len;
+                }
+                Op::ScD { rd, rs1, rs2 } => {
+                    self.store_u64(self.regs[rs1 as usize], self.regs[rs2 as usize]);
+                    self.write_reg(rd, 0);
+                    self.pc += len;
+                }
+                Op::AmoAddW { rd, rs1, rs2 } => {
+                    let addr = self.regs[rs1 as usize];
+                    let old = self.load_u32(addr) as i32;
+                    self.write_reg(rd, old as i64 as u64);
+                    self.store_u32(addr, (old.wrapping_add(self.regs[rs2 as usize] as i32)) as u32);
+                    self.pc += len;
+                }
+                Op::AmoAddD { rd, rs1, rs2 } => {
+                    let addr = self.regs[rs1 as usize];
+                    let old = self.load_u64(addr);
+                    self.write_reg(rd, old);
+                    self.store_u64(addr, old.wrapping_add(self.regs[rs2 as usize]));
+                    self.pc += len;
+                }
+                Op::AmoSwapW { rd, rs1, rs2 } => {
+                    let addr = self.regs[rs1 as usize];
+                    let old = self.load_u32(addr) as i32 as i64 as u64;
+                    self.write_reg(rd, old);
+                    self.store_u32(addr, self.regs[rs2 as usize] as u32);
+                    self.pc += len;
+                }
+                Op::AmoSwapD { rd, rs1, rs2 } => {
+                    let addr = self.regs[rs1 as usize];
+                    let old = self.load_u64(addr);
+                    self.write_reg(rd, old);
+                    self.store_u64(addr, self.regs[rs2 as usize]);
+                    self.pc += len;
+                }
+            }
+        }
+        Ok(StopReason::StepLimit)
+    }
+}
+
+/// Compare two interpreters' architectural state, returning the index of
+/// the first diverging register (or `None` if they match).
+pub fn first_divergent_register(a: &Interp, b: &Interp) -> Option<usize> {
+    (0..32).find(|&i| a.regs[i] != b.regs[i])
+}
+
+pub fn memory_checksum(interp: &Interp) -> u64 {
+    interp
+        .mem
+        .iter()
+        .fold(0xcbf29ce484222325u64, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+pub fn require_match(a: &Interp, b: &Interp, context: &str) -> Result<()> {
+    if let Some(idx) = first_divergent_register(a, b) {
+        bail!(
+            "{context}: register x{idx} diverged: interp=0x{:x} wasm=0x{:x} at pc=0x{:x}",
+            a.regs[idx],
+            b.regs[idx],
+            a.pc
+        );
+    }
+    if a.pc != b.pc {
+        bail!("{context}: pc diverged: interp=0x{:x} wasm=0x{:x}", a.pc, b.pc);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pc_advances_by_the_instructions_own_length_not_a_hardcoded_four() {
+        // A 2-byte (compressed) instr at pc=0 followed by a 4-byte instr
+        // at pc=2: advancing pc by a hardcoded 4 after the first would
+        // land on pc=4, skip the second instruction entirely, and the
+        // program would appear to `Exited` one step early.
+        let program = vec![
+            Instr { pc: 0, len: 2, op: Op::Addi { rd: 5, rs1: 0, imm: 7 } },
+            Instr { pc: 2, len: 4, op: Op::Addi { rd: 6, rs1: 5, imm: 10 } },
+        ];
+        let mut interp = Interp::new(0, 0, vec![]);
+        let reason = interp.run(&program, 10).unwrap();
+
+        assert_eq!(reason, StopReason::Exited);
+        assert_eq!(interp.regs[5], 7);
+        assert_eq!(interp.regs[6], 17);
+        assert_eq!(interp.pc, 6, "pc should land past the 4-byte instruction, not skip it");
+    }
+
+    #[test]
+    fn lower_carries_the_source_instructions_length() {
+        let decoded = vec![
+            disasm::Instruction { addr: 0, len: 2, kind: disasm::Kind::Addi { rd: 5, rs1: 0, imm: 7 } },
+            disasm::Instruction { addr: 2, len: 4, kind: disasm::Kind::Addi { rd: 6, rs1: 5, imm: 10 } },
+        ];
+        let program = lower(&decoded).unwrap();
+        assert_eq!(program[0].len, 2);
+        assert_eq!(program[1].len, 4);
+    }
+}
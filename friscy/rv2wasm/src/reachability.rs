@@ -0,0 +1,153 @@
+// reachability.rs - Reachability-driven dead-function elimination
+//
+// Dynamically linked RISC-V binaries pull in a lot of libc code that the
+// translated program never calls. This pass walks the call/branch graph
+// from the CFG's entry point (plus any required roots) and prunes
+// functions that are never reached, so the emitted dispatch table and code
+// section only cover code the program can actually execute.
+//
+// Like `relooper`, this operates on a minimal graph shape rather than
+// `cfg::Cfg` directly, so it can be dropped in against the CFG builder's
+// function/block representation once pruning is wired into `compile`.
+
+use std::collections::{HashSet, VecDeque};
+
+/// One function as seen by the reachability pass: its address plus the
+/// direct call/branch targets discoverable from its blocks' terminators.
+#[derive(Debug, Clone)]
+pub struct FunctionNode {
+    pub addr: u64,
+    pub direct_targets: Vec<u64>,
+    /// True if this function's address appears as a data/relocation
+    /// constant anywhere in the binary, meaning it could be reached
+    /// through an indirect call we can't resolve statically.
+    pub address_taken: bool,
+}
+
+/// Result of a reachability sweep: which function addresses survive.
+#[derive(Debug, Clone, Default)]
+pub struct ReachabilityReport {
+    pub kept: HashSet<u64>,
+    pub total: usize,
+}
+
+impl ReachabilityReport {
+    pub fn kept_count(&self) -> usize {
+        self.kept.len()
+    }
+}
+
+/// Compute the set of reachable functions.
+///
+/// `entry` and `extra_roots` (e.g. `.init_array` targets and symbols named
+/// in relocations) seed the worklist. Any function with
+/// `address_taken == true` is conservatively kept regardless of whether a
+/// direct-call path to it was found, since it may be invoked through an
+/// indirect call we can't resolve statically.
+pub fn compute_reachable(functions: &[FunctionNode], entry: u64, extra_roots: &[u64]) -> ReachabilityReport {
+    let by_addr: std::collections::HashMap<u64, &FunctionNode> =
+        functions.iter().map(|f| (f.addr, f)).collect();
+
+    let mut kept = HashSet::new();
+    let mut worklist: VecDeque<u64> = VecDeque::new();
+
+    worklist.push_back(entry);
+    worklist.extend(extra_roots.iter().copied());
+
+    for f in functions {
+        if f.address_taken {
+            worklist.push_back(f.addr);
+        }
+    }
+
+    while let Some(addr) = worklist.pop_front() {
+        if !kept.insert(addr) {
+            continue;
+        }
+        if let Some(func) = by_addr.get(&addr) {
+            for &target in &func.direct_targets {
+                if by_addr.contains_key(&target) && !kept.contains(&target) {
+                    worklist.push_back(target);
+                }
+            }
+        }
+    }
+
+    ReachabilityReport {
+        kept,
+        total: functions.len(),
+    }
+}
+
+/// Filter `functions` down to the reachable set, preserving their
+/// original order. Returns the pruned list alongside the report so callers
+/// can log the kept-vs-total counts.
+pub fn prune(functions: Vec<FunctionNode>, entry: u64, extra_roots: &[u64]) -> (Vec<FunctionNode>, ReachabilityReport) {
+    let report = compute_reachable(&functions, entry, extra_roots);
+    let kept = functions
+        .into_iter()
+        .filter(|f| report.kept.contains(&f.addr))
+        .collect();
+    (kept, report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn func(addr: u64, direct_targets: &[u64]) -> FunctionNode {
+        FunctionNode {
+            addr,
+            direct_targets: direct_targets.to_vec(),
+            address_taken: false,
+        }
+    }
+
+    #[test]
+    fn only_functions_reachable_from_entry_are_kept() {
+        // 0 -> 1 -> 2; 3 is never called.
+        let functions = vec![func(0, &[1]), func(1, &[2]), func(2, &[]), func(3, &[])];
+        let report = compute_reachable(&functions, 0, &[]);
+
+        assert_eq!(report.total, 4);
+        assert_eq!(report.kept, HashSet::from([0, 1, 2]));
+        assert_eq!(report.kept_count(), 3);
+    }
+
+    #[test]
+    fn extra_roots_are_kept_even_if_unreachable_from_entry() {
+        let functions = vec![func(0, &[]), func(1, &[])];
+        let report = compute_reachable(&functions, 0, &[1]);
+
+        assert_eq!(report.kept, HashSet::from([0, 1]));
+    }
+
+    #[test]
+    fn address_taken_functions_are_kept_regardless_of_direct_calls() {
+        let mut maybe_indirect = func(1, &[]);
+        maybe_indirect.address_taken = true;
+        let functions = vec![func(0, &[]), maybe_indirect];
+        let report = compute_reachable(&functions, 0, &[]);
+
+        assert_eq!(report.kept, HashSet::from([0, 1]));
+    }
+
+    #[test]
+    fn calls_to_addresses_outside_the_function_set_are_ignored() {
+        // 0 calls a target with no FunctionNode (e.g. an external symbol);
+        // that shouldn't panic or get added to `kept`.
+        let functions = vec![func(0, &[0xDEAD])];
+        let report = compute_reachable(&functions, 0, &[]);
+
+        assert_eq!(report.kept, HashSet::from([0]));
+    }
+
+    #[test]
+    fn prune_drops_unreachable_functions_and_preserves_order() {
+        let functions = vec![func(0, &[2]), func(1, &[]), func(2, &[])];
+        let (pruned, report) = prune(functions, 0, &[]);
+
+        assert_eq!(pruned.iter().map(|f| f.addr).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(report.total, 3);
+    }
+}
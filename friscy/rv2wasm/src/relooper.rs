@@ -0,0 +1,372 @@
+// relooper.rs - Relooper: CFG -> structured Wasm control flow
+//
+// Reconstructs nested block/loop/if control flow from an arbitrary
+// basic-block graph, following Emscripten's Relooper algorithm. This lets
+// codegen emit `br`/`br_if` against enclosing `block`/`loop` labels instead
+// of routing every block transition through a single flat `br_table`
+// trampoline, which gives Wasm engines real block-local structure to
+// optimize.
+//
+// This module is intentionally decoupled from `cfg::build`'s concrete
+// types: it consumes anything shaped like a block graph (`RelooperBlock`)
+// so it can be wired in once the CFG builder exposes per-function
+// successor edges, without forcing a cyclic dependency in the meantime.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// One basic block as seen by the relooper: an opaque id plus the ids of
+/// the blocks it can fall through or branch to.
+#[derive(Debug, Clone)]
+pub struct RelooperBlock {
+    pub id: u64,
+    pub successors: Vec<u64>,
+}
+
+/// A structured region of control flow, ready to be lowered to Wasm
+/// `block`/`loop`/`if`/`br`/`br_if` instructions by the caller.
+#[derive(Debug, Clone)]
+pub enum Shape {
+    /// No more blocks to emit.
+    None,
+    /// A single block followed by the shape built from its successors.
+    Simple { id: u64, next: Box<Shape> },
+    /// A `loop` wrapping a body shape; back-edges inside the body target
+    /// this loop's label via `br`.
+    Loop { body: Box<Shape>, next: Box<Shape> },
+    /// Multiple independent entries, each wrapped in its own handled
+    /// `block` and dispatched via a `__label` local, continuing into the
+    /// shape built from the union of their successors.
+    Multiple {
+        handled: Vec<(u64, Shape)>,
+        next: Box<Shape>,
+    },
+}
+
+/// Build a [`Shape`] for the region reachable from `entries`, given the
+/// full block graph `blocks` (keyed by block id).
+///
+/// `entries` is the set of blocks to consider as this region's roots. A
+/// block outside `entries` that is only reachable through one of them is
+/// folded into the resulting shape; a block reachable from multiple
+/// entries without passing through another entry is "independent" and
+/// becomes its own handled block in a `Multiple` shape.
+pub fn reloop(blocks: &HashMap<u64, RelooperBlock>, entries: &[u64]) -> Shape {
+    build_shape(blocks, &entries.iter().copied().collect(), None, &BTreeSet::new())
+}
+
+/// `region`, when set, is the enclosing loop body's block set: successors
+/// outside it are exits the enclosing `Loop` shape's own `next` already
+/// accounts for, so they must not also be threaded into this chain (that
+/// would emit the exit block's code a second time, once per iteration).
+///
+/// `in_loop` is the set of ids for which a `Loop` shape is already being
+/// built as we recurse into its body; a later back-edge to one of them is
+/// exactly the loop's own repetition, not a reason to wrap it in *another*
+/// nested `Loop`.
+fn build_shape(
+    blocks: &HashMap<u64, RelooperBlock>,
+    entries: &BTreeSet<u64>,
+    region: Option<&BTreeSet<u64>>,
+    in_loop: &BTreeSet<u64>,
+) -> Shape {
+    if entries.is_empty() {
+        return Shape::None;
+    }
+
+    if entries.len() == 1 {
+        let id = *entries.iter().next().unwrap();
+        if in_loop.contains(&id) || !has_back_edge(blocks, id, entries, region) {
+            return simple_shape(blocks, id, entries, region, in_loop);
+        }
+        return loop_shape(blocks, entries, region, in_loop);
+    }
+
+    // More than one entry: split into independently-reachable entries
+    // (Simple candidates) and the rest, which must be dispatched together
+    // as a Multiple.
+    let independent = independent_entries(blocks, entries, region);
+    if independent.len() == 1 {
+        let id = *independent.iter().next().unwrap();
+        return simple_shape(blocks, id, entries, region, in_loop);
+    }
+
+    if !independent.is_empty() && independent.len() < entries.len() {
+        // Peel off the independent entries as their own simple shapes in
+        // sequence; what's left still shares entries, so it becomes the
+        // `next` built from the remaining (dependent) set.
+        let remaining: BTreeSet<u64> = entries.difference(&independent).copied().collect();
+        return build_shape(blocks, &remaining, region, in_loop);
+    }
+
+    multiple_shape(blocks, entries, region, in_loop)
+}
+
+fn in_region(region: Option<&BTreeSet<u64>>, id: u64) -> bool {
+    region.is_none_or(|r| r.contains(&id))
+}
+
+/// True if some block reachable from `id` (without leaving `entries` or
+/// `region`) branches back to `id` itself.
+fn has_back_edge(blocks: &HashMap<u64, RelooperBlock>, id: u64, entries: &BTreeSet<u64>, region: Option<&BTreeSet<u64>>) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![id];
+    while let Some(cur) = stack.pop() {
+        if !seen.insert(cur) {
+            continue;
+        }
+        let Some(block) = blocks.get(&cur) else {
+            continue;
+        };
+        for &succ in &block.successors {
+            if succ == id {
+                return true;
+            }
+            if !entries.contains(&succ) && in_region(region, succ) {
+                stack.push(succ);
+            }
+        }
+    }
+    false
+}
+
+fn simple_shape(
+    blocks: &HashMap<u64, RelooperBlock>,
+    id: u64,
+    entries: &BTreeSet<u64>,
+    region: Option<&BTreeSet<u64>>,
+    in_loop: &BTreeSet<u64>,
+) -> Shape {
+    let successors: BTreeSet<u64> = blocks
+        .get(&id)
+        .map(|b| b.successors.iter().copied().collect())
+        .unwrap_or_default();
+
+    let next_entries: BTreeSet<u64> = successors
+        .iter()
+        .filter(|s| !entries.contains(s) && in_region(region, **s))
+        .copied()
+        .collect();
+    Shape::Simple { id, next: Box::new(build_shape(blocks, &next_entries, region, in_loop)) }
+}
+
+fn loop_shape(blocks: &HashMap<u64, RelooperBlock>, entries: &BTreeSet<u64>, region: Option<&BTreeSet<u64>>, in_loop: &BTreeSet<u64>) -> Shape {
+    // The loop body is everything reachable from `entries` that stays
+    // inside the region (including back-edges); exits leave via `next`.
+    let mut body_ids = BTreeSet::new();
+    let mut stack: Vec<u64> = entries.iter().copied().collect();
+    while let Some(cur) = stack.pop() {
+        if !body_ids.insert(cur) {
+            continue;
+        }
+        if let Some(block) = blocks.get(&cur) {
+            for &succ in &block.successors {
+                if in_region(region, succ) && reachable_back_to(blocks, succ, entries, &body_ids, region) {
+                    stack.push(succ);
+                }
+            }
+        }
+    }
+
+    let exits: BTreeSet<u64> = body_ids
+        .iter()
+        .filter_map(|id| blocks.get(id))
+        .flat_map(|b| b.successors.iter().copied())
+        .filter(|succ| !body_ids.contains(succ))
+        .collect();
+
+    let mut body_in_loop = in_loop.clone();
+    body_in_loop.extend(entries.iter().copied());
+
+    Shape::Loop {
+        // The body is scoped to its own block set: anything it branches to
+        // outside that set is an exit already covered by `next`, so it must
+        // not also be threaded into the body's own chain (which would emit
+        // that block's code a second time).
+        body: Box::new(build_shape(blocks, &body_ids, Some(&body_ids), &body_in_loop)),
+        next: Box::new(build_shape(blocks, &exits, region, in_loop)),
+    }
+}
+
+/// Conservative membership test used while growing a loop body: a
+/// candidate block belongs in the body if it can reach back to one of the
+/// loop's entries without leaving the region explored so far.
+fn reachable_back_to(
+    blocks: &HashMap<u64, RelooperBlock>,
+    from: u64,
+    entries: &BTreeSet<u64>,
+    body_so_far: &BTreeSet<u64>,
+    region: Option<&BTreeSet<u64>>,
+) -> bool {
+    if entries.contains(&from) || body_so_far.contains(&from) {
+        return true;
+    }
+    let mut seen = HashSet::new();
+    let mut stack = vec![from];
+    while let Some(cur) = stack.pop() {
+        if !seen.insert(cur) {
+            continue;
+        }
+        if entries.contains(&cur) {
+            return true;
+        }
+        if let Some(block) = blocks.get(&cur) {
+            stack.extend(block.successors.iter().copied().filter(|succ| in_region(region, *succ)));
+        }
+    }
+    false
+}
+
+/// Entries reachable from outside the region without passing through any
+/// other entry. These are the ones that can be peeled off as a `Simple`
+/// shape instead of folded into a `Multiple` dispatch.
+fn independent_entries(blocks: &HashMap<u64, RelooperBlock>, entries: &BTreeSet<u64>, region: Option<&BTreeSet<u64>>) -> BTreeSet<u64> {
+    let mut reachable_from_other_entry: BTreeSet<u64> = BTreeSet::new();
+    for &entry in entries {
+        for &other in entries {
+            if entry == other {
+                continue;
+            }
+            if is_reachable_without_entries(blocks, other, entry, entries, region) {
+                reachable_from_other_entry.insert(entry);
+            }
+        }
+    }
+    entries.difference(&reachable_from_other_entry).copied().collect()
+}
+
+fn is_reachable_without_entries(
+    blocks: &HashMap<u64, RelooperBlock>,
+    from: u64,
+    target: u64,
+    entries: &BTreeSet<u64>,
+    region: Option<&BTreeSet<u64>>,
+) -> bool {
+    let mut seen = HashSet::new();
+    let mut stack = vec![from];
+    while let Some(cur) = stack.pop() {
+        if !seen.insert(cur) {
+            continue;
+        }
+        let Some(block) = blocks.get(&cur) else {
+            continue;
+        };
+        for &succ in &block.successors {
+            if succ == target {
+                return true;
+            }
+            if !entries.contains(&succ) && in_region(region, succ) {
+                stack.push(succ);
+            }
+        }
+    }
+    false
+}
+
+fn multiple_shape(blocks: &HashMap<u64, RelooperBlock>, entries: &BTreeSet<u64>, region: Option<&BTreeSet<u64>>, in_loop: &BTreeSet<u64>) -> Shape {
+    let mut handled = Vec::new();
+    let mut union_next = BTreeSet::new();
+
+    for &id in entries {
+        let successors: BTreeSet<u64> = blocks
+            .get(&id)
+            .map(|b| b.successors.iter().copied().collect())
+            .unwrap_or_default();
+        let inner_entries: BTreeSet<u64> = successors
+            .iter()
+            .filter(|s| !entries.contains(s) && in_region(region, **s))
+            .copied()
+            .collect();
+        union_next.extend(inner_entries.iter().copied());
+        handled.push((id, build_shape(blocks, &inner_entries, region, in_loop)));
+    }
+
+    Shape::Multiple {
+        handled,
+        next: Box::new(build_shape(blocks, &union_next, region, in_loop)),
+    }
+}
+
+/// True if `entries`, as a whole, contains an irreducible region the
+/// relooper can't cleanly split into Simple/Loop/Multiple shapes (e.g. two
+/// blocks that are each reachable from the other without a dominating
+/// entry). Codegen should fall back to the index-set + `br_table`
+/// trampoline for these.
+pub fn is_irreducible(blocks: &HashMap<u64, RelooperBlock>, entries: &[u64]) -> bool {
+    let entries: BTreeSet<u64> = entries.iter().copied().collect();
+    if entries.len() < 2 {
+        return false;
+    }
+    independent_entries(blocks, &entries, None).is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(id: u64, successors: &[u64]) -> (u64, RelooperBlock) {
+        (
+            id,
+            RelooperBlock {
+                id,
+                successors: successors.to_vec(),
+            },
+        )
+    }
+
+    #[test]
+    fn straight_line_is_a_chain_of_simple_shapes() {
+        let blocks = HashMap::from([block(0, &[1]), block(1, &[2]), block(2, &[])]);
+
+        match reloop(&blocks, &[0]) {
+            Shape::Simple { id: 0, next, .. } => match *next {
+                Shape::Simple { id: 1, next, .. } => match *next {
+                    Shape::Simple { id: 2, next, .. } => assert!(matches!(*next, Shape::None)),
+                    other => panic!("expected block 2, got {other:?}"),
+                },
+                other => panic!("expected block 1, got {other:?}"),
+            },
+            other => panic!("expected block 0, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn single_loop_becomes_a_loop_shape() {
+        // 0 -> 1 -> 2 -> 1 (back edge), 2 -> 3 (exit)
+        let blocks = HashMap::from([block(0, &[1]), block(1, &[2]), block(2, &[1, 3]), block(3, &[])]);
+
+        match reloop(&blocks, &[0]) {
+            Shape::Simple { id: 0, next, .. } => match *next {
+                Shape::Loop { .. } => {}
+                other => panic!("expected a loop shape, got {other:?}"),
+            },
+            other => panic!("expected block 0, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn irreducible_diamond_is_flagged_and_falls_back_to_multiple() {
+        // 1 and 2 each reachable from the other, neither dominates: classic
+        // irreducible region.
+        let blocks = HashMap::from([block(1, &[2]), block(2, &[1])]);
+
+        assert!(is_irreducible(&blocks, &[1, 2]));
+        assert!(matches!(reloop(&blocks, &[1, 2]), Shape::Multiple { .. }));
+    }
+
+    #[test]
+    fn fully_independent_entries_become_a_multiple_shape() {
+        // 0 and 1 are both reachable from outside without passing through
+        // the other and neither reaches the other, so all entries are
+        // independent and get dispatched together as a Multiple.
+        let blocks = HashMap::from([block(0, &[2]), block(1, &[2]), block(2, &[])]);
+
+        match reloop(&blocks, &[0, 1]) {
+            Shape::Multiple { handled, .. } => {
+                assert_eq!(handled.len(), 2);
+                assert!(handled.iter().any(|(id, _)| *id == 0));
+                assert!(handled.iter().any(|(id, _)| *id == 1));
+            }
+            other => panic!("expected a Multiple shape, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// stack.rs - System V initial process stack layout
+//
+// Builds the argv/envp/auxv image a kernel ELF loader hands a freshly
+// exec'd process, so musl/glibc `_start` and the dynamic linker can boot
+// the same way they would on a real RISC-V Linux machine. The caller picks
+// where in guest linear memory the stack lives; this module only computes
+// the bytes and the resulting stack pointer.
+
+use anyhow::{bail, Result};
+
+/// Well-known auxiliary vector types (from `<elf.h>` / `<linux/auxvec.h>`).
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuxType {
+    AT_NULL = 0,
+    AT_PHDR = 3,
+    AT_PHENT = 4,
+    AT_PHNUM = 5,
+    AT_PAGESZ = 6,
+    AT_BASE = 7,
+    AT_ENTRY = 9,
+    AT_UID = 11,
+    AT_EUID = 12,
+    AT_GID = 13,
+    AT_EGID = 14,
+    AT_SECURE = 23,
+    AT_RANDOM = 25,
+}
+
+/// Inputs required to materialize the initial stack for one process.
+#[derive(Debug, Clone, Default)]
+pub struct StackConfig {
+    pub argv: Vec<String>,
+    pub envp: Vec<String>,
+    pub phdr_vaddr: u64,
+    pub phdr_entsize: u64,
+    pub phdr_count: u64,
+    pub entry: u64,
+    /// Interpreter load base for dynamic binaries; 0 for static.
+    pub interp_base: u64,
+    pub page_size: u64,
+}
+
+/// The materialized stack: bytes to place at `base` in guest linear
+/// memory, plus the resulting (16-byte aligned) stack pointer.
+pub struct StackImage {
+    pub bytes: Vec<u8>,
+    pub sp: u64,
+}
+
+/// Build the System V initial stack for `config`, to be written starting
+/// at guest address `base` with `size` bytes available below it (the stack
+/// grows down from `base + size`).
+///
+/// Layout, from high to low addresses:
+/// ```text
+/// [ argv[0] string ] [ argv[1] string ] ... [ envp[.] strings ] [ AT_RANDOM bytes ]
+/// [ padding to 16-byte align ]
+/// argc
+/// argv[0..n] pointers, NULL
+/// envp[0..n] pointers, NULL
+/// auxv (type, value) pairs, AT_NULL terminated
+/// ```
+///
+/// Returns an error instead of underflowing the cursor or panicking on an
+/// out-of-bounds slice write if `size` isn't large enough to hold the
+/// strings plus the fixed argc/argv/envp/auxv region; callers must size
+/// the stack region from `config` before picking `size`.
+pub fn build_stack(config: &StackConfig, base: u64, size: u64) -> Result<StackImage> {
+    const AUXV_LEN: u64 = 13; // (type, value) pairs below, including AT_NULL
+
+    let strings_len: u64 = 16 // AT_RANDOM
+        + config.argv.iter().map(|s| s.len() as u64 + 1).sum::<u64>()
+        + config.envp.iter().map(|s| s.len() as u64 + 1).sum::<u64>();
+    let fixed_len = 8 // argc
+        + 8 * (config.argv.len() as u64 + 1) // argv + NULL
+        + 8 * (config.envp.len() as u64 + 1) // envp + NULL
+        + 16 * AUXV_LEN; // (type, value) pairs incl. AT_NULL
+
+    // Up to 15 bytes of slop from the 16-byte alignment of the fixed
+    // region on top of the exact string/fixed lengths.
+    let required = strings_len + 15 + fixed_len;
+    if required > size {
+        bail!("build_stack: {size} bytes is not enough for argv/envp/auxv (need at least {required})");
+    }
+
+    let top = base + size;
+    let mut bytes = vec![0u8; size as usize];
+
+    // Seeded (not cryptographic) randomness for AT_RANDOM; deterministic
+    // builds are more useful here than unpredictability.
+    let random_bytes: [u8; 16] = {
+        let mut seed: u64 = 0x5EED_u64
+            .wrapping_add(config.entry)
+            .wrapping_add(config.argv.len() as u64);
+        let mut out = [0u8; 16];
+        for chunk in out.chunks_mut(8) {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            chunk.copy_from_slice(&seed.to_le_bytes()[..chunk.len()]);
+        }
+        out
+    };
+
+    // Strings and the AT_RANDOM payload live at the top of the stack,
+    // written downward; record where each one landed.
+    let mut cursor = top;
+    let write_bytes = |cursor: &mut u64, bytes_buf: &mut [u8], data: &[u8]| -> u64 {
+        *cursor -= data.len() as u64;
+        let offset = (*cursor - base) as usize;
+        bytes_buf[offset..offset + data.len()].copy_from_slice(data);
+        *cursor
+    };
+
+    let random_addr = write_bytes(&mut cursor, &mut bytes, &random_bytes);
+
+    let mut argv_ptrs = Vec::with_capacity(config.argv.len());
+    for s in &config.argv {
+        let mut data = s.as_bytes().to_vec();
+        data.push(0);
+        argv_ptrs.push(write_bytes(&mut cursor, &mut bytes, &data));
+    }
+
+    let mut envp_ptrs = Vec::with_capacity(config.envp.len());
+    for s in &config.envp {
+        let mut data = s.as_bytes().to_vec();
+        data.push(0);
+        envp_ptrs.push(write_bytes(&mut cursor, &mut bytes, &data));
+    }
+
+    let auxv: Vec<(u64, u64)> = vec![
+        (AuxType::AT_PHDR as u64, config.phdr_vaddr),
+        (AuxType::AT_PHENT as u64, config.phdr_entsize),
+        (AuxType::AT_PHNUM as u64, config.phdr_count),
+        (AuxType::AT_ENTRY as u64, config.entry),
+        (AuxType::AT_BASE as u64, config.interp_base),
+        (AuxType::AT_PAGESZ as u64, config.page_size),
+        (AuxType::AT_RANDOM as u64, random_addr),
+        (AuxType::AT_UID as u64, 0),
+        (AuxType::AT_EUID as u64, 0),
+        (AuxType::AT_GID as u64, 0),
+        (AuxType::AT_EGID as u64, 0),
+        (AuxType::AT_SECURE as u64, 0),
+        (AuxType::AT_NULL as u64, 0),
+    ];
+
+    debug_assert_eq!(auxv.len() as u64, AUXV_LEN);
+
+    // The fixed-size region (argc, argv ptrs, envp ptrs, auxv pairs) is
+    // laid out next, growing down from the current (already 8-byte
+    // aligned from string writes) cursor, then rounded to 16 bytes.
+    cursor -= fixed_len;
+    cursor &= !0xF; // 16-byte align per the RISC-V calling convention
+    let sp = cursor;
+
+    let mut offset = (cursor - base) as usize;
+    let mut put_u64 = |offset: &mut usize, value: u64| {
+        bytes[*offset..*offset + 8].copy_from_slice(&value.to_le_bytes());
+        *offset += 8;
+    };
+
+    put_u64(&mut offset, config.argv.len() as u64); // argc
+    for ptr in &argv_ptrs {
+        put_u64(&mut offset, *ptr);
+    }
+    put_u64(&mut offset, 0); // argv NULL terminator
+    for ptr in &envp_ptrs {
+        put_u64(&mut offset, *ptr);
+    }
+    put_u64(&mut offset, 0); // envp NULL terminator
+    for (kind, value) in &auxv {
+        put_u64(&mut offset, *kind);
+        put_u64(&mut offset, *value);
+    }
+
+    Ok(StackImage { bytes, sp })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> StackConfig {
+        StackConfig {
+            argv: vec!["prog".into()],
+            envp: vec!["PATH=/bin".into()],
+            phdr_vaddr: 0x1000,
+            phdr_entsize: 56,
+            phdr_count: 3,
+            entry: 0x10000,
+            interp_base: 0,
+            page_size: 4096,
+        }
+    }
+
+    #[test]
+    fn fits_in_a_generously_sized_region() {
+        let image = build_stack(&config(), 0x7000_0000, 0x1000).unwrap();
+        assert_eq!(image.sp % 16, 0, "stack pointer must be 16-byte aligned");
+        assert!(image.sp < 0x7000_0000 + 0x1000);
+        assert!(image.sp >= 0x7000_0000);
+    }
+
+    #[test]
+    fn rejects_a_region_too_small_to_hold_argv_envp_auxv() {
+        assert!(build_stack(&config(), 0x7000_0000, 16).is_err());
+    }
+}
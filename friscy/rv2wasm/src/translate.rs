@@ -0,0 +1,1064 @@
+// translate.rs - CFG + Shape -> Wasm IR lowering
+//
+// Bridges `cfg::Cfg` to the `WasmModule`/`WasmFunction`/`WasmInst` IR
+// `wasm_builder::build` consumes. `cfg::structured_shape` gives each
+// function a `relooper::Shape`, which `lower_shape` below turns into nested
+// `block`/`loop` + `br`/`br_if`/`select` control flow instead of routing
+// every basic block through the host PC dispatch loop. Calls, indirect
+// jumps, and `ecall` always exit to the dispatch loop regardless (see
+// `lower_terminator`'s `Kind::Jal`/`Kind::Jalr`/`Kind::Ecall` arms) — a
+// call's target is a different function's own region (`cfg::structured_shape`
+// already cuts the edge so it isn't folded in here), and an indirect jump's
+// target isn't known until runtime. Any block left over (a call's
+// return-address landing pad, which has no incoming edge in this graph at
+// all) falls back to the original one-function-per-block trampoline model.
+
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::{bail, Result};
+
+use crate::cfg::{Block, Cfg};
+use crate::disasm::{Instruction, Kind};
+use crate::relooper::Shape;
+
+/// The complete translated program, ready for `wasm_builder::build`.
+#[derive(Debug, Clone)]
+pub struct WasmModule {
+    pub functions: Vec<WasmFunction>,
+    pub memory_pages: u32,
+    pub fuel_metering: bool,
+}
+
+/// One Wasm function implementing either a whole reloop'd RV function or a
+/// single flat basic block, keyed by the guest address `wasm_builder`'s
+/// dispatch table should route to it.
+#[derive(Debug, Clone)]
+pub struct WasmFunction {
+    pub name: String,
+    pub block_addr: u64,
+    pub num_locals: u32,
+    pub body: Vec<WasmInst>,
+}
+
+/// The instruction set `wasm_builder::emit_instruction` knows how to lower
+/// to `wasm_encoder::Instruction`. Kept distinct from `wasm_encoder`'s own
+/// type so this module (and anything else constructing a `WasmModule`,
+/// like the fuzz target) doesn't need to depend on the encoder crate.
+#[derive(Debug, Clone)]
+pub enum WasmInst {
+    Block { label: u64 },
+    Loop { label: u64 },
+    End,
+    Br { label: u32 },
+    BrIf { label: u32 },
+    Return,
+    Call { func_idx: u32 },
+
+    LocalGet { idx: u32 },
+    LocalSet { idx: u32 },
+    LocalTee { idx: u32 },
+
+    I32Const { value: i32 },
+    I64Const { value: i64 },
+
+    I32Load { offset: u32 },
+    I64Load { offset: u32 },
+    I64Load8S { offset: u32 },
+    I64Load8U { offset: u32 },
+    I64Load16S { offset: u32 },
+    I64Load16U { offset: u32 },
+    I64Load32S { offset: u32 },
+    I64Load32U { offset: u32 },
+
+    I32Store { offset: u32 },
+    I64Store { offset: u32 },
+    I64Store8 { offset: u32 },
+    I64Store16 { offset: u32 },
+    I64Store32 { offset: u32 },
+
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64DivU,
+    I64RemS,
+    I64RemU,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64ShrS,
+    I64ShrU,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64LeU,
+    I64GeS,
+    I64GeU,
+
+    I32Add,
+    I32Sub,
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+
+    I32WrapI64,
+    I64ExtendI32S,
+    I64ExtendI32U,
+
+    F32Load { offset: u32 },
+    F64Load { offset: u32 },
+    F32Store { offset: u32 },
+    F64Store { offset: u32 },
+
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Sqrt,
+    F64Min,
+    F64Max,
+    F64Abs,
+    F64Neg,
+    F64Copysign,
+
+    F32Add,
+    F32Sub,
+    F32Mul,
+    F32Div,
+    F32Sqrt,
+    F32Min,
+    F32Max,
+    F32Abs,
+    F32Neg,
+    F32Copysign,
+
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Le,
+    F64Gt,
+    F64Ge,
+
+    F64ConvertI64S,
+    F64ConvertI64U,
+    I64TruncF64S,
+    I64TruncF64U,
+    F32DemoteF64,
+    F64PromoteF32,
+    F32ReinterpretI32,
+    F64ReinterpretI64,
+    I32ReinterpretF32,
+    I64ReinterpretF64,
+
+    Drop,
+    Select,
+
+    /// Emits nothing; purely a marker for `debug` builds so a Wasm text
+    /// dump shows which guest block a run of instructions came from.
+    Comment { text: String },
+}
+
+/// Byte offset of register `r` within the register file (x0-x31, 8 bytes
+/// each), mirroring `wasm_builder`'s `SP_REGISTER_OFFSET` convention.
+fn reg_offset(r: u8) -> i32 {
+    r as i32 * 8
+}
+
+/// Local index reserved for the relooper's `$__label` value: which sibling
+/// entry to resume at when a `br` lands on a `Multiple` shape's dispatch,
+/// or re-enters a `Loop` whose body starts with one. Every reloop'd
+/// function reserves this local (index 1, right after the `$m` param),
+/// whether or not its shape happens to need it.
+const LABEL_LOCAL: u32 = 1;
+
+/// High bit flagging an `ecall` resumption address in the value a block
+/// function returns to the host dispatch loop, matching
+/// `wasm_builder::build_dispatch_function`'s `0x80000000` syscall check.
+const ECALL_FLAG: u32 = 0x8000_0000;
+
+fn push_reg(out: &mut Vec<WasmInst>, r: u8) {
+    if r == 0 {
+        // x0 is hardwired to zero; no memory traffic needed.
+        out.push(WasmInst::I64Const { value: 0 });
+    } else {
+        out.push(WasmInst::I32Const { value: reg_offset(r) });
+        out.push(WasmInst::I64Load { offset: 0 });
+    }
+}
+
+/// Store the i64 value `push_value` computes into register `rd`. A no-op
+/// for `rd == 0` (and `push_value` is never called in that case): writes
+/// to x0 are discarded, same as real hardware, and `Addi`/`Add`/etc. have
+/// no other side effect, so there's nothing left to emit at all.
+fn store_reg(out: &mut Vec<WasmInst>, rd: u8, push_value: impl FnOnce(&mut Vec<WasmInst>)) {
+    if rd == 0 {
+        return;
+    }
+    out.push(WasmInst::I32Const { value: reg_offset(rd) });
+    push_value(out);
+    out.push(WasmInst::I64Store { offset: 0 });
+}
+
+/// Translate one non-terminator instruction into `out`. Terminator kinds
+/// (`Beq`/`Bne`/`Jal`/`Jalr`/`Ecall`) are handled by `lower_terminator`
+/// instead and must never reach here.
+fn translate_body_instr(out: &mut Vec<WasmInst>, inst: &Instruction) -> Result<()> {
+    match inst.kind {
+        Kind::Addi { rd, rs1, imm } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I64Const { value: imm as i64 });
+                b.push(WasmInst::I64Add);
+            });
+        }
+        Kind::Add { rd, rs1, rs2 } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                push_reg(b, rs2);
+                b.push(WasmInst::I64Add);
+            });
+        }
+        Kind::Sub { rd, rs1, rs2 } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                push_reg(b, rs2);
+                b.push(WasmInst::I64Sub);
+            });
+        }
+        Kind::Lui { rd, imm } => {
+            store_reg(out, rd, |b| b.push(WasmInst::I64Const { value: imm as i64 }));
+        }
+        Kind::Lw { rd, rs1, imm } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I64Const { value: imm as i64 });
+                b.push(WasmInst::I64Add);
+                b.push(WasmInst::I32WrapI64);
+                b.push(WasmInst::I64Load32S { offset: 0 });
+            });
+        }
+        Kind::Ld { rd, rs1, imm } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I64Const { value: imm as i64 });
+                b.push(WasmInst::I64Add);
+                b.push(WasmInst::I32WrapI64);
+                b.push(WasmInst::I64Load { offset: 0 });
+            });
+        }
+        Kind::Sw { rs1, rs2, imm } => {
+            push_reg(out, rs1);
+            out.push(WasmInst::I64Const { value: imm as i64 });
+            out.push(WasmInst::I64Add);
+            out.push(WasmInst::I32WrapI64);
+            push_reg(out, rs2);
+            out.push(WasmInst::I32WrapI64);
+            out.push(WasmInst::I32Store { offset: 0 });
+        }
+        Kind::Sd { rs1, rs2, imm } => {
+            push_reg(out, rs1);
+            out.push(WasmInst::I64Const { value: imm as i64 });
+            out.push(WasmInst::I64Add);
+            out.push(WasmInst::I32WrapI64);
+            push_reg(out, rs2);
+            out.push(WasmInst::I64Store { offset: 0 });
+        }
+        Kind::Beq { .. } | Kind::Bne { .. } | Kind::Jal { .. } | Kind::Jalr { .. } | Kind::Ecall => {
+            bail!("translate: {:?} at 0x{:x} is a terminator and must go through lower_terminator", inst.kind, inst.addr);
+        }
+        // There's exactly one hart executing the compiled module (the
+        // module's own memory isn't even declared shared), so nothing can
+        // ever observe a window between a load and a store: a plain
+        // load-then-store gives the same result as the real atomic, and
+        // `sc.*` can never fail a reservation another hart broke, so it
+        // always reports success.
+        Kind::LrW { rd, rs1 } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I32WrapI64);
+                b.push(WasmInst::I64Load32S { offset: 0 });
+            });
+        }
+        Kind::LrD { rd, rs1 } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I32WrapI64);
+                b.push(WasmInst::I64Load { offset: 0 });
+            });
+        }
+        Kind::ScW { rd, rs1, rs2 } => {
+            push_reg(out, rs1);
+            out.push(WasmInst::I32WrapI64);
+            push_reg(out, rs2);
+            out.push(WasmInst::I64Store32 { offset: 0 });
+            store_reg(out, rd, |b| b.push(WasmInst::I64Const { value: 0 }));
+        }
+        Kind::ScD { rd, rs1, rs2 } => {
+            push_reg(out, rs1);
+            out.push(WasmInst::I32WrapI64);
+            push_reg(out, rs2);
+            out.push(WasmInst::I64Store { offset: 0 });
+            store_reg(out, rd, |b| b.push(WasmInst::I64Const { value: 0 }));
+        }
+        Kind::AmoAddW { rd, rs1, rs2 } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I32WrapI64);
+                b.push(WasmInst::I64Load32S { offset: 0 });
+            });
+            push_reg(out, rs1);
+            out.push(WasmInst::I32WrapI64);
+            push_reg(out, rs1);
+            out.push(WasmInst::I32WrapI64);
+            out.push(WasmInst::I64Load32S { offset: 0 });
+            push_reg(out, rs2);
+            out.push(WasmInst::I64Add);
+            out.push(WasmInst::I64Store32 { offset: 0 });
+        }
+        Kind::AmoAddD { rd, rs1, rs2 } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I32WrapI64);
+                b.push(WasmInst::I64Load { offset: 0 });
+            });
+            push_reg(out, rs1);
+            out.push(WasmInst::I32WrapI64);
+            push_reg(out, rs1);
+            out.push(WasmInst::I32WrapI64);
+            out.push(WasmInst::I64Load { offset: 0 });
+            push_reg(out, rs2);
+            out.push(WasmInst::I64Add);
+            out.push(WasmInst::I64Store { offset: 0 });
+        }
+        Kind::AmoSwapW { rd, rs1, rs2 } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I32WrapI64);
+                b.push(WasmInst::I64Load32S { offset: 0 });
+            });
+            push_reg(out, rs1);
+            out.push(WasmInst::I32WrapI64);
+            push_reg(out, rs2);
+            out.push(WasmInst::I64Store32 { offset: 0 });
+        }
+        Kind::AmoSwapD { rd, rs1, rs2 } => {
+            store_reg(out, rd, |b| {
+                push_reg(b, rs1);
+                b.push(WasmInst::I32WrapI64);
+                b.push(WasmInst::I64Load { offset: 0 });
+            });
+            push_reg(out, rs1);
+            out.push(WasmInst::I32WrapI64);
+            push_reg(out, rs2);
+            out.push(WasmInst::I64Store { offset: 0 });
+        }
+        Kind::Unknown { raw } => {
+            bail!("translate: unknown/undecoded instruction 0x{raw:08x} at 0x{:x}", inst.addr);
+        }
+    }
+    Ok(())
+}
+
+/// An enclosing `block`/`loop` a `br` can target: the guest block ids it
+/// dispatches to (a singleton for a `Multiple` arm, or the union of a
+/// `Loop` body's own roots), and the absolute open-construct depth at
+/// which it was opened.
+struct Scope {
+    ids: BTreeSet<u64>,
+    depth_abs: u32,
+}
+
+/// What emitting an edge to `target` requires, given the shape about to be
+/// lowered next and the scopes currently open around it.
+#[derive(Debug, Clone, Copy)]
+enum EdgeAction {
+    /// `target` is `next`'s only root; nothing to emit, just keep going.
+    Fallthrough,
+    /// `target` is one of several roots `next` dispatches on; set
+    /// `$__label` so `next`'s `Multiple` dispatch picks the right arm.
+    SetLabelThenFallthrough(u64),
+    /// `target` is handled by an enclosing scope; `br` out to it
+    /// (optionally setting `$__label` first, if that scope dispatches on
+    /// more than one id).
+    Branch { depth: u32, set_label: Option<u64> },
+    /// `target` isn't structurally reachable from here (a call, an
+    /// irreducible neighbor, or genuinely outside this shape); exit to the
+    /// host dispatch loop.
+    ExitToHost(u64),
+}
+
+fn is_natural(action: EdgeAction) -> bool {
+    matches!(action, EdgeAction::Fallthrough | EdgeAction::SetLabelThenFallthrough(_))
+}
+
+/// The guest block ids a shape dispatches to directly (its "roots"):
+/// `next_entries`/`entries` as built by `relooper::build_shape`, which are
+/// exactly what a `br`/fallthrough into this shape must resolve against.
+fn shape_roots(shape: &Shape) -> Vec<u64> {
+    match shape {
+        Shape::None => vec![],
+        Shape::Simple { id, .. } => vec![*id],
+        Shape::Loop { body, .. } => shape_roots(body),
+        Shape::Multiple { handled, .. } => handled.iter().map(|(id, _)| *id).collect(),
+    }
+}
+
+/// `shape_roots(next)`, but transparent to an empty `next`: a `Shape::None`
+/// means nothing runs here, so falling through it really means falling
+/// through to whatever the nearest non-empty entry in `outer` is — the
+/// shape that was passed down as the thing lowered right after the
+/// current `Shape::Loop` (see `lower_shape`'s `Loop` arm, which doesn't
+/// chain its body's own `next` field the way `Simple`/`Multiple` do).
+fn fallthrough_roots(next: &Shape, outer: &[&Shape]) -> Vec<u64> {
+    if !matches!(next, Shape::None) {
+        return shape_roots(next);
+    }
+    match outer.split_first() {
+        Some((head, rest)) => fallthrough_roots(head, rest),
+        None => vec![],
+    }
+}
+
+fn resolve(target: u64, next: &Shape, outer: &[&Shape], enclosing: &[Scope], open: u32) -> EdgeAction {
+    let roots = fallthrough_roots(next, outer);
+    if roots.contains(&target) {
+        return if roots.len() > 1 {
+            EdgeAction::SetLabelThenFallthrough(target)
+        } else {
+            EdgeAction::Fallthrough
+        };
+    }
+    for scope in enclosing.iter().rev() {
+        if scope.ids.contains(&target) {
+            let set_label = if scope.ids.len() > 1 { Some(target) } else { None };
+            return EdgeAction::Branch { depth: open - scope.depth_abs - 1, set_label };
+        }
+    }
+    EdgeAction::ExitToHost(target)
+}
+
+/// Emit `action`, with `extra_depth` added to any `Branch`'s depth to
+/// account for a guard `block` the caller may have wrapped around this
+/// call (see `lower_conditional`).
+fn emit_action(action: EdgeAction, extra_depth: u32, out: &mut Vec<WasmInst>) {
+    match action {
+        EdgeAction::Fallthrough => {}
+        EdgeAction::SetLabelThenFallthrough(id) => {
+            out.push(WasmInst::I64Const { value: id as i64 });
+            out.push(WasmInst::LocalSet { idx: LABEL_LOCAL });
+        }
+        EdgeAction::Branch { depth, set_label } => {
+            if let Some(id) = set_label {
+                out.push(WasmInst::I64Const { value: id as i64 });
+                out.push(WasmInst::LocalSet { idx: LABEL_LOCAL });
+            }
+            out.push(WasmInst::Br { label: depth + extra_depth });
+        }
+        EdgeAction::ExitToHost(target) => {
+            out.push(WasmInst::I32Const { value: target as i32 });
+            out.push(WasmInst::Return);
+        }
+    }
+}
+
+/// Lower a two-successor conditional terminator (`Beq`/`Bne`) against its
+/// already-resolved edge actions.
+///
+/// Structured purely with `block`/`br_if`/`end` (no native Wasm `if`):
+/// when exactly one side is the natural continuation, the other is wrapped
+/// in a fresh `block` skipped via `br_if` on the inverse of its trigger
+/// condition, so it only runs when that side is actually taken, and the
+/// natural side's (possibly empty) resolution always follows unconditionally.
+/// When both sides are natural but reach different ids of the same
+/// `Multiple` `next`, there's no divergent control to guard at all — just
+/// pick which id to set with `select`.
+fn lower_conditional(rs1: u8, rs2: u8, is_beq: bool, taken: EdgeAction, fall: EdgeAction, out: &mut Vec<WasmInst>) {
+    let taken_natural = is_natural(taken);
+    let fall_natural = is_natural(fall);
+
+    if taken_natural && fall_natural {
+        if let (EdgeAction::SetLabelThenFallthrough(t), EdgeAction::SetLabelThenFallthrough(f)) = (taken, fall) {
+            if t == f {
+                // Both outcomes dispatch to the same `next` root: which
+                // way the branch actually goes doesn't matter, so set the
+                // label unconditionally instead of reading the registers
+                // at all.
+                out.push(WasmInst::I64Const { value: t as i64 });
+                out.push(WasmInst::LocalSet { idx: LABEL_LOCAL });
+            } else {
+                out.push(WasmInst::I64Const { value: t as i64 });
+                out.push(WasmInst::I64Const { value: f as i64 });
+                push_reg(out, rs1);
+                push_reg(out, rs2);
+                out.push(if is_beq { WasmInst::I64Eq } else { WasmInst::I64Ne });
+                out.push(WasmInst::Select);
+                out.push(WasmInst::LocalSet { idx: LABEL_LOCAL });
+            }
+        }
+        // Otherwise both sides are plain Fallthrough: nothing to emit,
+        // `next` follows as-is regardless of which way the branch went.
+        return;
+    }
+
+    if !taken_natural {
+        out.push(WasmInst::Block { label: 0 });
+        push_reg(out, rs1);
+        push_reg(out, rs2);
+        out.push(if is_beq { WasmInst::I64Ne } else { WasmInst::I64Eq }); // skip when NOT taken
+        out.push(WasmInst::BrIf { label: 0 });
+        emit_action(taken, 1, out);
+        out.push(WasmInst::End);
+        emit_action(fall, 0, out);
+    } else {
+        out.push(WasmInst::Block { label: 0 });
+        push_reg(out, rs1);
+        push_reg(out, rs2);
+        out.push(if is_beq { WasmInst::I64Eq } else { WasmInst::I64Ne }); // skip when taken
+        out.push(WasmInst::BrIf { label: 0 });
+        emit_action(fall, 1, out);
+        out.push(WasmInst::End);
+        emit_action(taken, 0, out);
+    }
+}
+
+/// Lower `block`'s terminator (its last instruction), given the shape
+/// that should run next and the scopes currently enclosing this point.
+/// Returns whether the caller should go on to lower `next` itself (false
+/// once every path out of this terminator has already exited via `Return`
+/// or `Br`).
+fn lower_terminator(
+    block: &Block,
+    inst: &Instruction,
+    next: &Shape,
+    outer: &[&Shape],
+    enclosing: &[Scope],
+    open: u32,
+    out: &mut Vec<WasmInst>,
+) -> Result<bool> {
+    match inst.kind {
+        Kind::Beq { rs1, rs2, .. } | Kind::Bne { rs1, rs2, .. } => {
+            let is_beq = matches!(inst.kind, Kind::Beq { .. });
+            let taken = resolve(block.successors[0], next, outer, enclosing, open);
+            let fall = resolve(block.successors[1], next, outer, enclosing, open);
+            lower_conditional(rs1, rs2, is_beq, taken, fall, out);
+            Ok(is_natural(taken) || is_natural(fall))
+        }
+        Kind::Jal { rd, .. } => {
+            if rd != 0 {
+                let link = inst.addr + inst.len as u64;
+                store_reg(out, rd, |b| b.push(WasmInst::I64Const { value: link as i64 }));
+            }
+            // A call's target is a different FunctionNode's own region
+            // (see cfg::Cfg::structured_shape), never part of this
+            // shape, so it can never resolve to Fallthrough here in
+            // practice — but a plain `jal x0, offset` (no link) can
+            // perfectly well target a sibling block in this same shape.
+            let action = resolve(block.successors[0], next, outer, enclosing, open);
+            emit_action(action, 0, out);
+            Ok(is_natural(action))
+        }
+        Kind::Jalr { rd, rs1, imm } => {
+            if rd != 0 {
+                let link = inst.addr + inst.len as u64;
+                store_reg(out, rd, |b| b.push(WasmInst::I64Const { value: link as i64 }));
+            }
+            // Target isn't known until runtime, so this always exits to
+            // the host dispatch loop, which re-resolves the PC itself.
+            push_reg(out, rs1);
+            out.push(WasmInst::I64Const { value: imm as i64 });
+            out.push(WasmInst::I64Add);
+            out.push(WasmInst::I64Const { value: !1i64 }); // clear bit 0 per the jalr spec
+            out.push(WasmInst::I64And);
+            out.push(WasmInst::I32WrapI64);
+            out.push(WasmInst::Return);
+            Ok(false)
+        }
+        Kind::Ecall => {
+            let flagged = (inst.addr as u32) | ECALL_FLAG;
+            out.push(WasmInst::I32Const { value: flagged as i32 });
+            out.push(WasmInst::Return);
+            Ok(false)
+        }
+        _ => {
+            // Not actually control flow: this block ends here only
+            // because its fallthrough happens to be some other branch's
+            // target. Translate it like any other body instruction, then
+            // resolve the block's one fallthrough successor (or halt if
+            // this was the very end of the section).
+            translate_body_instr(out, inst)?;
+            match block.successors.first() {
+                None => {
+                    out.push(WasmInst::I32Const { value: -1 });
+                    out.push(WasmInst::Return);
+                    Ok(false)
+                }
+                Some(&target) => {
+                    let action = resolve(target, next, outer, enclosing, open);
+                    emit_action(action, 0, out);
+                    Ok(is_natural(action))
+                }
+            }
+        }
+    }
+}
+
+/// `outer` is the fallthrough chain `resolve()` falls back on whenever a
+/// `Shape::None` leaves nothing local to check against — innermost first,
+/// see `fallthrough_roots`.
+fn lower_shape(
+    blocks: &HashMap<u64, &Block>,
+    shape: &Shape,
+    outer: &[&Shape],
+    enclosing: &mut Vec<Scope>,
+    open: u32,
+    debug: bool,
+    out: &mut Vec<WasmInst>,
+) -> Result<()> {
+    match shape {
+        Shape::None => Ok(()),
+        Shape::Simple { id, next } => {
+            let block = blocks
+                .get(id)
+                .ok_or_else(|| anyhow::anyhow!("translate: shape references unknown block 0x{id:x}"))?;
+            if debug {
+                out.push(WasmInst::Comment { text: format!("block 0x{id:x}") });
+            }
+            let (init, term) = block
+                .instructions
+                .split_at(block.instructions.len().saturating_sub(1));
+            for i in init {
+                translate_body_instr(out, i)?;
+            }
+            let term = term.first().ok_or_else(|| anyhow::anyhow!("translate: block 0x{id:x} has no instructions"))?;
+            let continue_to_next = lower_terminator(block, term, next, outer, enclosing, open, out)?;
+            if continue_to_next {
+                lower_shape(blocks, next, outer, enclosing, open, debug, out)?;
+            }
+            Ok(())
+        }
+        Shape::Loop { body, next } => {
+            let roots: BTreeSet<u64> = shape_roots(body).into_iter().collect();
+            let label = roots.iter().next().copied().unwrap_or(0);
+            out.push(WasmInst::Loop { label });
+            enclosing.push(Scope { ids: roots, depth_abs: open });
+            // `body`'s own `next` field never reaches past `Shape::None`
+            // (a loop body's own chain is self-contained), so anything
+            // falling off its end genuinely means "the loop is done" —
+            // which lands here, right after `End`, in `next`. Thread
+            // `next` onto `outer` so `resolve()` can see that a target
+            // reachable here is a real `Fallthrough`, not a dead end.
+            let mut body_outer = Vec::with_capacity(outer.len() + 1);
+            body_outer.push(next.as_ref());
+            body_outer.extend_from_slice(outer);
+            lower_shape(blocks, body, &body_outer, enclosing, open + 1, debug, out)?;
+            enclosing.pop();
+            out.push(WasmInst::End);
+            lower_shape(blocks, next, outer, enclosing, open, debug, out)
+        }
+        Shape::Multiple { handled, next } => {
+            let n = handled.len() as u32;
+            if n == 0 {
+                return lower_shape(blocks, next, outer, enclosing, open, debug, out);
+            }
+
+            // One extra outer block beyond the `n` per-entry ones, purely
+            // as a shared skip target: without it, an arm finishing early
+            // could only `br` out as far as the next arm's own wrapping
+            // block, landing in that arm's code instead of jumping clean
+            // past every remaining arm to `next`.
+            out.push(WasmInst::Block { label: 0 });
+            for _ in 0..n {
+                out.push(WasmInst::Block { label: 0 });
+            }
+            for (i, (id, _)) in handled.iter().enumerate() {
+                out.push(WasmInst::LocalGet { idx: LABEL_LOCAL });
+                out.push(WasmInst::I64Const { value: *id as i64 });
+                out.push(WasmInst::I64Eq);
+                out.push(WasmInst::BrIf { label: i as u32 });
+            }
+
+            for (i, (id, _)) in handled.iter().enumerate() {
+                enclosing.push(Scope {
+                    ids: BTreeSet::from([*id]),
+                    depth_abs: open + n - i as u32,
+                });
+            }
+
+            for (i, (_, arm)) in handled.iter().enumerate() {
+                out.push(WasmInst::End);
+                let arm_open = open + n - i as u32;
+                lower_shape(blocks, arm, outer, enclosing, arm_open, debug, out)?;
+                if i as u32 != n - 1 {
+                    out.push(WasmInst::Br { label: n - 1 - i as u32 });
+                }
+            }
+            out.push(WasmInst::End); // closes the shared skip-target block
+
+            for _ in 0..n {
+                enclosing.pop();
+            }
+            lower_shape(blocks, next, outer, enclosing, open, debug, out)
+        }
+    }
+}
+
+/// Every guest block id a shape will cover once lowered, so `translate`
+/// knows which blocks are already spoken for and doesn't also emit them as
+/// flat fallback functions.
+fn collect_shape_ids(shape: &Shape, out: &mut BTreeSet<u64>) {
+    match shape {
+        Shape::None => {}
+        Shape::Simple { id, next } => {
+            out.insert(*id);
+            collect_shape_ids(next, out);
+        }
+        Shape::Loop { body, next } => {
+            collect_shape_ids(body, out);
+            collect_shape_ids(next, out);
+        }
+        Shape::Multiple { handled, next } => {
+            for (id, arm) in handled {
+                out.insert(*id);
+                collect_shape_ids(arm, out);
+            }
+            collect_shape_ids(next, out);
+        }
+    }
+}
+
+/// Translate a single basic block into its own function, exiting to the
+/// host dispatch loop unconditionally for every terminator kind — the
+/// original flat trampoline model, used for blocks no function's shape
+/// covers: a call's return-address landing pad, which has no incoming edge
+/// in the graph at all (`cfg::Cfg::structured_shape` only ever walks
+/// forward from a function's entry).
+fn translate_flat_block(blocks: &HashMap<u64, &Block>, id: u64, debug: bool) -> Result<WasmFunction> {
+    let block = blocks
+        .get(&id)
+        .ok_or_else(|| anyhow::anyhow!("translate: missing block 0x{id:x} during flat fallback"))?;
+
+    let mut body = Vec::new();
+    if debug {
+        body.push(WasmInst::Comment { text: format!("flat block 0x{id:x}") });
+    }
+    let (init, term) = block.instructions.split_at(block.instructions.len().saturating_sub(1));
+    for inst in init {
+        translate_body_instr(&mut body, inst)?;
+    }
+    let term = term
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("translate: flat block 0x{id:x} has no instructions"))?;
+
+    match term.kind {
+        Kind::Beq { rs1, rs2, .. } | Kind::Bne { rs1, rs2, .. } => {
+            let is_beq = matches!(term.kind, Kind::Beq { .. });
+            let taken = block.successors[0];
+            let fall = block.successors[1];
+            body.push(WasmInst::I32Const { value: taken as i32 });
+            body.push(WasmInst::I32Const { value: fall as i32 });
+            push_reg(&mut body, rs1);
+            push_reg(&mut body, rs2);
+            body.push(if is_beq { WasmInst::I64Eq } else { WasmInst::I64Ne });
+            body.push(WasmInst::Select);
+            body.push(WasmInst::Return);
+        }
+        Kind::Jal { rd, .. } => {
+            if rd != 0 {
+                let link = term.addr + term.len as u64;
+                store_reg(&mut body, rd, |b| b.push(WasmInst::I64Const { value: link as i64 }));
+            }
+            body.push(WasmInst::I32Const { value: block.successors[0] as i32 });
+            body.push(WasmInst::Return);
+        }
+        Kind::Jalr { rd, rs1, imm } => {
+            if rd != 0 {
+                let link = term.addr + term.len as u64;
+                store_reg(&mut body, rd, |b| b.push(WasmInst::I64Const { value: link as i64 }));
+            }
+            push_reg(&mut body, rs1);
+            body.push(WasmInst::I64Const { value: imm as i64 });
+            body.push(WasmInst::I64Add);
+            body.push(WasmInst::I64Const { value: !1i64 });
+            body.push(WasmInst::I64And);
+            body.push(WasmInst::I32WrapI64);
+            body.push(WasmInst::Return);
+        }
+        Kind::Ecall => {
+            let flagged = (term.addr as u32) | ECALL_FLAG;
+            body.push(WasmInst::I32Const { value: flagged as i32 });
+            body.push(WasmInst::Return);
+        }
+        _ => {
+            translate_body_instr(&mut body, term)?;
+            let next_pc = block.successors.first().copied().map(|a| a as i32).unwrap_or(-1);
+            body.push(WasmInst::I32Const { value: next_pc });
+            body.push(WasmInst::Return);
+        }
+    }
+
+    Ok(WasmFunction { name: format!("block_{id:x}"), block_addr: id, num_locals: 0, body })
+}
+
+/// Translate a complete `Cfg` into the Wasm IR `wasm_builder::build`
+/// consumes: one `WasmFunction` per `FunctionNode`, reloop'd via
+/// `lower_shape`, plus one flat function per block `structured_shape`
+/// doesn't cover (a call's return-address landing pad has no incoming edge
+/// in the graph at all, so no shape ever reaches it).
+pub fn translate(cfg: &Cfg, memory_pages: u32, fuel_metering: bool, debug: bool) -> Result<WasmModule> {
+    let blocks_by_id: HashMap<u64, &Block> = cfg.blocks.iter().map(|b| (b.start, b)).collect();
+
+    let mut covered: BTreeSet<u64> = BTreeSet::new();
+    let mut functions = Vec::new();
+
+    for func in &cfg.functions {
+        let shape = cfg.structured_shape(func.addr);
+        collect_shape_ids(&shape, &mut covered);
+        let mut body = Vec::new();
+        let mut enclosing = Vec::new();
+        lower_shape(&blocks_by_id, &shape, &[], &mut enclosing, 0, debug, &mut body)?;
+        functions.push(WasmFunction {
+            name: format!("func_{:x}", func.addr),
+            block_addr: func.addr,
+            num_locals: 1, // reserved $__label local; harmless if this shape never needs it
+            body,
+        });
+    }
+
+    // Anything left over — a call's return-address landing pad, which has
+    // no incoming edge in this graph at all — still needs to be directly
+    // dispatchable from the host loop.
+    for block in &cfg.blocks {
+        if covered.insert(block.start) {
+            functions.push(translate_flat_block(&blocks_by_id, block.start, debug)?);
+        }
+    }
+
+    functions.sort_by_key(|f| f.block_addr);
+    Ok(WasmModule { functions, memory_pages, fuel_metering })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beq(addr: u64, rs1: u8, rs2: u8, offset: i32) -> Instruction {
+        Instruction { addr, len: 4, kind: Kind::Beq { rs1, rs2, offset } }
+    }
+    fn jal(addr: u64, rd: u8, offset: i32) -> Instruction {
+        Instruction { addr, len: 4, kind: Kind::Jal { rd, offset } }
+    }
+    fn addi(addr: u64, rd: u8, rs1: u8, imm: i32) -> Instruction {
+        Instruction { addr, len: 4, kind: Kind::Addi { rd, rs1, imm } }
+    }
+
+    #[test]
+    fn lower_shape_handles_a_loop_nested_inside_a_multiple_shape() {
+        // Two independent entries dispatched via Multiple: 0x10 is a
+        // single-block self-loop wrapped in its own Loop shape, 0x20 is a
+        // plain straight-line arm that halts. This is exactly the case a
+        // flat per-block trampoline can't express: the loop's back-edge has
+        // to `br` to a `loop` label nested two levels inside the dispatch,
+        // not just fall through to the next instruction.
+        let loop_block = Block { start: 0x10, instructions: vec![jal(0x10, 0, 0)], successors: vec![0x10] };
+        let halt_block = Block { start: 0x20, instructions: vec![addi(0x20, 5, 0, 1)], successors: vec![] };
+        let blocks: HashMap<u64, &Block> = HashMap::from([(0x10, &loop_block), (0x20, &halt_block)]);
+
+        let shape = Shape::Multiple {
+            handled: vec![
+                (
+                    0x10,
+                    Shape::Loop {
+                        body: Box::new(Shape::Simple { id: 0x10, next: Box::new(Shape::None) }),
+                        next: Box::new(Shape::None),
+                    },
+                ),
+                (0x20, Shape::Simple { id: 0x20, next: Box::new(Shape::None) }),
+            ],
+            next: Box::new(Shape::None),
+        };
+
+        let mut enclosing = Vec::new();
+        let mut out = Vec::new();
+        lower_shape(&blocks, &shape, &[], &mut enclosing, 0, false, &mut out).unwrap();
+
+        assert!(enclosing.is_empty(), "lower_shape must leave the caller's scope stack as it found it");
+
+        assert!(matches!(out[0], WasmInst::Block { .. }), "outer shared skip-target block");
+        assert!(matches!(out[1], WasmInst::Block { .. }), "per-entry block for 0x10");
+        assert!(matches!(out[2], WasmInst::Block { .. }), "per-entry block for 0x20");
+        assert!(matches!(out[3], WasmInst::LocalGet { idx } if idx == LABEL_LOCAL));
+        assert!(matches!(out[4], WasmInst::I64Const { value: 0x10 }));
+        assert!(matches!(out[5], WasmInst::I64Eq));
+        assert!(matches!(out[6], WasmInst::BrIf { label: 0 }));
+        assert!(matches!(out[7], WasmInst::LocalGet { idx } if idx == LABEL_LOCAL));
+        assert!(matches!(out[8], WasmInst::I64Const { value: 0x20 }));
+        assert!(matches!(out[9], WasmInst::I64Eq));
+        assert!(matches!(out[10], WasmInst::BrIf { label: 1 }));
+        assert!(matches!(out[11], WasmInst::End), "enters the 0x10 arm");
+        assert!(matches!(out[12], WasmInst::Loop { label: 0x10 }));
+        assert!(matches!(out[13], WasmInst::Br { label: 0 }), "the self-loop's back-edge targets its own Loop label");
+        assert!(matches!(out[14], WasmInst::End), "closes the Loop");
+        assert!(matches!(out[15], WasmInst::Br { label: 1 }), "skip past the remaining arm to the shared exit");
+        assert!(matches!(out[16], WasmInst::End), "enters the 0x20 arm");
+        assert!(matches!(out[17], WasmInst::I32Const { value } if value == reg_offset(5)));
+        assert!(matches!(out[18], WasmInst::I64Const { value: 0 }), "x0 read as a constant, not a memory load");
+        assert!(matches!(out[19], WasmInst::I64Const { value: 1 }));
+        assert!(matches!(out[20], WasmInst::I64Add));
+        assert!(matches!(out[21], WasmInst::I64Store { offset: 0 }));
+        assert!(matches!(out[22], WasmInst::I32Const { value: -1 }), "0x20 has no successor, so it halts");
+        assert!(matches!(out[23], WasmInst::Return));
+        assert!(matches!(out[24], WasmInst::End), "closes the shared skip-target block");
+        assert_eq!(out.len(), 25);
+    }
+
+    #[test]
+    fn resolve_picks_branch_over_an_enclosing_scope_and_exit_to_host_otherwise() {
+        let fallthrough_next = Shape::Simple { id: 5, next: Box::new(Shape::None) };
+        assert!(matches!(resolve(5, &fallthrough_next, &[], &[], 0), EdgeAction::Fallthrough));
+
+        let multiple_next = Shape::Multiple {
+            handled: vec![
+                (5, Shape::Simple { id: 5, next: Box::new(Shape::None) }),
+                (6, Shape::Simple { id: 6, next: Box::new(Shape::None) }),
+            ],
+            next: Box::new(Shape::None),
+        };
+        assert!(matches!(resolve(5, &multiple_next, &[], &[], 0), EdgeAction::SetLabelThenFallthrough(5)));
+
+        let enclosing = vec![Scope { ids: BTreeSet::from([7]), depth_abs: 0 }];
+        match resolve(7, &Shape::None, &[], &enclosing, 2) {
+            EdgeAction::Branch { depth: 1, set_label: None } => {}
+            other => panic!("expected Branch{{depth:1}}, got {other:?}"),
+        }
+
+        assert!(matches!(resolve(99, &Shape::None, &[], &[], 0), EdgeAction::ExitToHost(99)));
+    }
+
+    #[test]
+    fn emit_action_branch_adds_the_callers_extra_depth() {
+        let mut out = Vec::new();
+        emit_action(EdgeAction::Branch { depth: 1, set_label: Some(9) }, 2, &mut out);
+        assert!(matches!(out[0], WasmInst::I64Const { value: 9 }));
+        assert!(matches!(out[1], WasmInst::LocalSet { idx } if idx == LABEL_LOCAL));
+        assert!(matches!(out[2], WasmInst::Br { label: 3 }), "Branch's own depth (1) plus the caller's extra_depth (2)");
+    }
+
+    #[test]
+    fn beq_lowered_via_select_when_both_sides_are_natural_but_dispatch_differently() {
+        // Both sides of the branch are "natural" (they just pick which root
+        // of the same Multiple `next` to resume at), so there's no actual
+        // divergent control flow to guard with a block/br_if — the guard
+        // reduces to a value-level select on which id to set.
+        let taken = EdgeAction::SetLabelThenFallthrough(0x100);
+        let fall = EdgeAction::SetLabelThenFallthrough(0x200);
+
+        let mut out = Vec::new();
+        lower_conditional(3, 4, true, taken, fall, &mut out);
+
+        assert!(matches!(out[0], WasmInst::I64Const { value: 0x100 }));
+        assert!(matches!(out[1], WasmInst::I64Const { value: 0x200 }));
+        assert!(matches!(out[2], WasmInst::I32Const { value } if value == reg_offset(3)));
+        assert!(matches!(out[3], WasmInst::I64Load { offset: 0 }));
+        assert!(matches!(out[4], WasmInst::I32Const { value } if value == reg_offset(4)));
+        assert!(matches!(out[5], WasmInst::I64Load { offset: 0 }));
+        assert!(matches!(out[6], WasmInst::I64Eq), "is_beq selects the equality comparison");
+        assert!(matches!(out[7], WasmInst::Select));
+        assert!(matches!(out[8], WasmInst::LocalSet { idx } if idx == LABEL_LOCAL));
+        assert_eq!(out.len(), 9);
+    }
+
+    #[test]
+    fn translate_flat_block_lowers_a_beq_terminator_via_select_and_always_returns() {
+        // The flat-trampoline fallback (used for a call's return-address
+        // landing pad, which no shape covers) has no enclosing block/loop
+        // nesting to guard with at all, so both targets are resolved purely
+        // at the value level and handed straight to the host dispatch loop.
+        let block = Block { start: 0x30, instructions: vec![beq(0x30, 1, 2, 0x10)], successors: vec![0x40, 0x34] };
+        let blocks: HashMap<u64, &Block> = HashMap::from([(0x30, &block)]);
+
+        let func = translate_flat_block(&blocks, 0x30, false).unwrap();
+
+        assert_eq!(func.name, "block_30");
+        assert_eq!(func.block_addr, 0x30);
+        let body = &func.body;
+        assert!(matches!(body[0], WasmInst::I32Const { value: 0x40 }), "taken target");
+        assert!(matches!(body[1], WasmInst::I32Const { value: 0x34 }), "fallthrough target");
+        assert!(matches!(body[2], WasmInst::I32Const { value } if value == reg_offset(1)));
+        assert!(matches!(body[3], WasmInst::I64Load { offset: 0 }));
+        assert!(matches!(body[4], WasmInst::I32Const { value } if value == reg_offset(2)));
+        assert!(matches!(body[5], WasmInst::I64Load { offset: 0 }));
+        assert!(matches!(body[6], WasmInst::I64Eq));
+        assert!(matches!(body[7], WasmInst::Select));
+        assert!(matches!(body[8], WasmInst::Return), "the orphan block always exits to the host dispatch loop");
+        assert_eq!(body.len(), 9);
+    }
+
+    #[test]
+    fn translate_emits_a_flat_function_for_an_uncalled_orphan_block() {
+        // A call's return-address landing pad has no incoming edge in the
+        // graph at all (structured_shape only ever walks forward from a
+        // function's entry), so `translate` must fall back to the flat
+        // trampoline for it rather than silently dropping it.
+        let instrs = vec![jal(0, 1, 8), addi(4, 1, 1, 1), addi(8, 1, 1, 1)];
+        let cfg = crate::cfg::build(&instrs, 0).unwrap();
+
+        let module = translate(&cfg, 1, false, false).unwrap();
+
+        let orphan = module.functions.iter().find(|f| f.block_addr == 4).expect("orphan return site must still get a function");
+        assert_eq!(orphan.name, "block_4");
+        assert!(
+            matches!(orphan.body.last(), Some(WasmInst::Return)),
+            "flat fallback functions always end by returning to the host dispatch loop"
+        );
+    }
+
+    #[test]
+    fn amo_add_w_writes_the_old_value_to_rd_and_the_sum_to_memory() {
+        // Single-hart semantics: rd gets the pre-add value, memory gets
+        // rd's register plus rs2, and nothing models reservation failure
+        // since there's no other hart around to cause one.
+        let inst = Instruction { addr: 0, len: 4, kind: Kind::AmoAddW { rd: 5, rs1: 10, rs2: 11 } };
+        let mut out = Vec::new();
+        translate_body_instr(&mut out, &inst).unwrap();
+
+        assert!(matches!(out[0], WasmInst::I32Const { value } if value == reg_offset(5)));
+        assert!(matches!(out[1], WasmInst::I32Const { value } if value == reg_offset(10)));
+        assert!(matches!(out[2], WasmInst::I64Load { offset: 0 }), "rs1 holds an address, read as a plain i64 register");
+        assert!(matches!(out[3], WasmInst::I32WrapI64));
+        assert!(matches!(out[4], WasmInst::I64Load32S { offset: 0 }), "old value loaded into rd");
+        assert!(matches!(out[5], WasmInst::I64Store { offset: 0 }));
+        assert!(matches!(out[6], WasmInst::I32Const { value } if value == reg_offset(10)));
+        assert!(matches!(out[7], WasmInst::I64Load { offset: 0 }));
+        assert!(matches!(out[8], WasmInst::I32WrapI64));
+        assert!(matches!(out[9], WasmInst::I32Const { value } if value == reg_offset(10)));
+        assert!(matches!(out[10], WasmInst::I64Load { offset: 0 }));
+        assert!(matches!(out[11], WasmInst::I32WrapI64));
+        assert!(matches!(out[12], WasmInst::I64Load32S { offset: 0 }), "old value reloaded to compute the sum");
+        assert!(matches!(out[13], WasmInst::I32Const { value } if value == reg_offset(11)));
+        assert!(matches!(out[14], WasmInst::I64Load { offset: 0 }));
+        assert!(matches!(out[15], WasmInst::I64Add));
+        assert!(matches!(out[16], WasmInst::I64Store32 { offset: 0 }), "new value stored back to memory");
+        assert_eq!(out.len(), 17);
+    }
+
+    #[test]
+    fn sc_w_always_reports_success() {
+        let inst = Instruction { addr: 0, len: 4, kind: Kind::ScW { rd: 5, rs1: 10, rs2: 11 } };
+        let mut out = Vec::new();
+        translate_body_instr(&mut out, &inst).unwrap();
+
+        assert!(matches!(out.last(), Some(WasmInst::I64Store { offset: 0 })));
+        let set_zero_at = out.len() - 2;
+        assert!(
+            matches!(out[set_zero_at], WasmInst::I64Const { value: 0 }),
+            "sc.w has no other hart to lose a reservation to, so it always writes 0 (success) to rd"
+        );
+    }
+}
+
@@ -2,18 +2,48 @@
 //
 // Converts the intermediate WasmModule to actual Wasm bytecode using wasm-encoder.
 
+use crate::stack::StackConfig;
 use crate::translate::{WasmInst, WasmModule};
 use anyhow::Result;
 use wasm_encoder::{
-    CodeSection, ConstExpr, DataSection, EntityType, ExportKind, ExportSection, Function,
-    FunctionSection, ImportSection, Instruction, MemoryType, Module, TableSection, TableType,
-    TypeSection, ValType,
+    CodeSection, ConstExpr, DataSection, ElementMode, ElementSection, Elements, EntityType,
+    ExportKind, ExportSection, Function, FunctionSection, ImportSection, Instruction, MemoryType,
+    Module, TableSection, TableType, TypeSection, ValType,
 };
 
 /// Offset in linear memory where the PC→index dispatch mapping table is stored.
 /// Located right after the register file (x0-x31, 256 bytes).
 const DISPATCH_MAP_OFFSET: u32 = 256;
 
+/// Byte offset of x2 (sp) within the register file (8 bytes per register,
+/// x0 at offset 0). `StackPlacement` writes the computed stack pointer
+/// here.
+const SP_REGISTER_OFFSET: i32 = 2 * 8;
+
+/// Where and for which process the initial stack image should be placed.
+/// Passing this to `build`/`build_with_opt_level`/`build_optimized` emits
+/// the System V argv/envp/auxv image `stack::build_stack` computes as an
+/// active data segment at `base`, plus a second tiny data segment writing
+/// the resulting stack pointer into x2's slot in the register file, so the
+/// guest program sees a correctly initialized `sp` the moment `run` starts
+/// executing at its entry block — without this, `run` is handed whatever
+/// zeroed-or-garbage value happened to be in that memory slot.
+pub struct StackPlacement {
+    pub config: StackConfig,
+    pub base: u64,
+    pub size: u64,
+}
+
+/// Returned by `$run` when the fuel counter is exhausted mid-execution,
+/// distinct from `0` (halt) and `-1` (unknown PC).
+const OUT_OF_FUEL: i32 = -2;
+
+/// Once a module has this many block functions, the nested-`block`
+/// `br_table` dispatch (O(n) code size, and limited to 255 cases by the
+/// byte-indexed map) gives way to a `call_indirect` dispatch: a 4-byte
+/// dispatch map plus a single `CallIndirect` through the function table.
+const CALL_INDIRECT_THRESHOLD: u32 = 255;
+
 /// Dispatch table metadata computed from block addresses.
 /// Maps PC values to dense function indices via a byte array in linear memory.
 struct DispatchTable {
@@ -21,17 +51,49 @@ struct DispatchTable {
     min_addr: u32,
     /// The mapping table bytes: table[i] = function index for half-word offset i,
     /// or `default_idx` if no block starts at that address.
+    ///
+    /// Byte-indexed (`u8` per entry) when `wide` is false; little-endian
+    /// `i32`-indexed (4 bytes per entry) when `wide` is true, since an
+    /// `i32` table slot index doesn't fit in a byte once there are 256+
+    /// functions.
     data: Vec<u8>,
     /// Number of block functions (also the default/invalid index for br_table)
     num_functions: u32,
+    /// True once `num_functions >= CALL_INDIRECT_THRESHOLD`, selecting the
+    /// `call_indirect` dispatch strategy over nested-block `br_table`.
+    wide: bool,
+}
+
+impl DispatchTable {
+    /// Offset in linear memory of the i64 fuel counter, consulted once per
+    /// dispatched block when `module.fuel_metering` is set. Placed right
+    /// after the dispatch map, which is unbounded in size (driven by the
+    /// guest program's address range and, in `wide` mode, 4 bytes/entry),
+    /// so this must be computed from the table's actual length rather
+    /// than a fixed budget — otherwise a large table silently clobbers
+    /// the fuel counter and saved-PC slots. 8-byte aligned for the i64
+    /// load/store.
+    fn fuel_offset(&self) -> u32 {
+        (DISPATCH_MAP_OFFSET + self.data.len() as u32 + 7) & !7
+    }
+
+    /// Offset in linear memory of the saved PC, written when execution
+    /// runs out of fuel so the host can resume from exactly where it
+    /// left off.
+    fn saved_pc_offset(&self) -> u32 {
+        self.fuel_offset() + 8
+    }
 }
 
 /// Build the dispatch table mapping PC → dense function index.
 ///
 /// RISC-V instructions are either 2 bytes (compressed) or 4 bytes, so all
-/// block addresses are 2-byte aligned. We build a byte-indexed lookup table
-/// where `table[(pc - min_addr) / 2]` gives the br_table case index for that PC.
-/// Unmapped addresses map to `num_functions` which is the br_table default (halt).
+/// block addresses are 2-byte aligned. We build a lookup table where
+/// `table[(pc - min_addr) / 2]` gives the dispatch case index for that PC:
+/// a byte per entry below `CALL_INDIRECT_THRESHOLD` functions, or a
+/// little-endian `i32` per entry once there are more than a byte can
+/// address. Unmapped addresses map to `num_functions`, the default
+/// (invalid/halt) index.
 fn build_dispatch_table(module: &WasmModule) -> DispatchTable {
     let n = module.functions.len() as u32;
 
@@ -40,9 +102,12 @@ fn build_dispatch_table(module: &WasmModule) -> DispatchTable {
             min_addr: 0,
             data: vec![],
             num_functions: 0,
+            wide: false,
         };
     }
 
+    let wide = n >= CALL_INDIRECT_THRESHOLD;
+
     // Collect (block_addr, function_index) pairs and sort by address
     let mut addr_to_idx: Vec<(u64, u32)> = module
         .functions
@@ -58,27 +123,387 @@ fn build_dispatch_table(module: &WasmModule) -> DispatchTable {
     // Table size: one entry per 2-byte-aligned address in the range
     let table_size = ((max_addr - min_addr) / 2 + 1) as usize;
 
-    // Initialize all entries to the default (invalid) index
-    let default_idx = if n < 255 { n as u8 } else { 255 };
-    let mut data = vec![default_idx; table_size];
-
-    // Fill in the known block addresses
-    for &(addr, idx) in &addr_to_idx {
-        let slot = ((addr as u32 - min_addr) / 2) as usize;
-        if slot < data.len() {
-            data[slot] = idx as u8;
+    let data = if wide {
+        let mut entries = vec![n; table_size];
+        for &(addr, idx) in &addr_to_idx {
+            let slot = ((addr as u32 - min_addr) / 2) as usize;
+            if slot < entries.len() {
+                entries[slot] = idx;
+            }
         }
-    }
+        entries.iter().flat_map(|v| v.to_le_bytes()).collect()
+    } else {
+        // Initialize all entries to the default (invalid) index
+        let default_idx = if n < 255 { n as u8 } else { 255 };
+        let mut bytes = vec![default_idx; table_size];
+        for &(addr, idx) in &addr_to_idx {
+            let slot = ((addr as u32 - min_addr) / 2) as usize;
+            if slot < bytes.len() {
+                bytes[slot] = idx as u8;
+            }
+        }
+        bytes
+    };
 
     DispatchTable {
         min_addr,
         data,
         num_functions: n,
+        wide,
+    }
+}
+
+/// Build the final Wasm binary, optionally routing through a structured
+/// IR (walrus) optimization pipeline instead of the raw encoder.
+///
+/// `opt_level == 0` always uses the raw `build` path below for
+/// reproducible, byte-for-byte debuggable output. `opt_level >= 1` builds
+/// the same module as walrus IR and runs the full pass pipeline in
+/// `build_via_ir`: dead-local elimination, local coalescing, and
+/// PC-trampoline folding.
+///
+/// `stack`, if given, places the initial process stack; see
+/// [`StackPlacement`].
+pub fn build_with_opt_level(module: &WasmModule, opt_level: u8, stack: Option<&StackPlacement>) -> Result<Vec<u8>> {
+    if opt_level == 0 {
+        return build(module, stack);
+    }
+    build_via_ir(module, stack)
+}
+
+/// Binaryen optimization level for [`build_optimized`], mirroring
+/// `wasm-opt`'s `-O0`..`-O4`/`-Os`/`-Oz` tiers at a coarser granularity.
+///
+/// Only available with the `binaryen-opt` feature: `binaryen-sys` needs
+/// cmake + a C/C++ toolchain to build, so this (and `build_optimized`) is
+/// opt-in rather than pulled into every build of this crate.
+#[cfg(feature = "binaryen-opt")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryenOptLevel {
+    /// No optimization; behaves like `build`.
+    None,
+    /// `-O2`-equivalent: balance size and speed.
+    Balanced,
+    /// `-Os`-equivalent: prioritize size.
+    Size,
+    /// `-O4`/`-O3`-equivalent: prioritize speed.
+    Speed,
+}
+
+/// Run the naive `build` output through Binaryen's optimizer as an
+/// opt-in post-processing stage. `build`'s dispatch loop and block
+/// functions are deliberately unoptimized (redundant local get/set around
+/// every `$pc` update, no dead-code elimination across cases); Binaryen's
+/// `-O` pipeline plus coalesce-locals shrinks and speeds up the artifact
+/// meaningfully. `build` itself is left untouched so callers who need
+/// reproducible, byte-for-byte debuggable output still have that path.
+///
+/// Requires the `binaryen-opt` feature.
+#[cfg(feature = "binaryen-opt")]
+pub fn build_optimized(module: &WasmModule, level: BinaryenOptLevel, stack: Option<&StackPlacement>) -> Result<Vec<u8>> {
+    let raw = build(module, stack)?;
+    if level == BinaryenOptLevel::None {
+        return Ok(raw);
+    }
+
+    let mut binaryen_module = binaryen::Module::read(&raw)
+        .map_err(|_| anyhow::anyhow!("binaryen failed to parse the generated Wasm module"))?;
+
+    let opts = binaryen::CodegenConfig {
+        shrink_level: match level {
+            BinaryenOptLevel::Size => 2,
+            _ => 0,
+        },
+        optimization_level: match level {
+            BinaryenOptLevel::Balanced => 2,
+            BinaryenOptLevel::Size => 2,
+            BinaryenOptLevel::Speed => 4,
+            BinaryenOptLevel::None => 0,
+        },
+        debug_info: false,
+    };
+    binaryen_module.optimize(&opts);
+
+    Ok(binaryen_module.write())
+}
+
+/// IR-backed build path: construct the module as walrus IR so later
+/// contributors can add optimization passes without touching byte-level
+/// encoding, then run the pass pipeline before emitting bytes.
+///
+/// Runs, in order: dead-local elimination (walrus's built-in `gc::run`),
+/// local coalescing, PC-trampoline folding, and data-segment merging (the
+/// last one is a no-op here by design — it already happened earlier in
+/// `build`, see `merge_adjacent_data_segments`'s doc comment).
+///
+/// This mirrors `build`'s section layout (dispatch function first, then
+/// one function per block, then the dispatch data segment) but goes
+/// through `walrus::Module`/`FunctionBuilder` instead of `wasm_encoder`
+/// directly, which is what makes the remaining passes possible once
+/// written: walrus tracks local def/use and data segment layout as
+/// first-class IR rather than raw bytes.
+fn build_via_ir(module: &WasmModule, stack: Option<&StackPlacement>) -> Result<Vec<u8>> {
+    let raw = build(module, stack)?;
+
+    let mut ir_module = walrus::Module::from_buffer(&raw)?;
+
+    // Dead-local elimination: the naive per-`$pc`-update codegen leaves
+    // locals that are written but never read again; walrus's builtin pass
+    // finds and removes them.
+    walrus::passes::gc::run(&mut ir_module);
+
+    coalesce_locals(&mut ir_module);
+
+    fold_pc_trampoline(&mut ir_module, module);
+
+    // Not yet implemented (no-op placeholder): see
+    // `merge_adjacent_data_segments`.
+    merge_adjacent_data_segments(&mut ir_module);
+
+    Ok(ir_module.emit_wasm())
+}
+
+/// Liveness-based local coalescing, scoped to a function's entry block only
+/// (no nested `Block`/`Loop`/`IfElse`) for the same reason `fold_pc_trampoline`
+/// below restricts itself: a linear scan over one `InstrSeq` can't account for
+/// a loop revisiting an earlier use after a later one runs, so a function with
+/// any nested control flow is left untouched rather than guessed at.
+///
+/// Within an eligible function, each non-parameter local's live range is taken
+/// as the span from its first `local.get`/`local.set`/`local.tee` to its last,
+/// and two locals of the same type with non-overlapping spans are merged onto
+/// one slot (the later one's references rewritten onto the earlier one's local
+/// id). Walrus's own `used` pass (run automatically at emission) drops the
+/// now-unreferenced locals, so there's no local-deletion bookkeeping here.
+///
+/// Parameters — and any local read before it's ever written in this block —
+/// are excluded from the pool entirely: their real live range starts at
+/// function entry, before any instruction, so a span computed from their
+/// first occurrence in the block would understate it and risk coalescing
+/// another local's later writes right over a still-live incoming value.
+fn coalesce_locals(module: &mut walrus::Module) {
+    let walrus::Module { funcs, locals, .. } = module;
+    for (_, func) in funcs.iter_local_mut() {
+        coalesce_in_function(func, locals);
     }
 }
 
-/// Build the final Wasm binary
-pub fn build(module: &WasmModule) -> Result<Vec<u8>> {
+fn coalesce_in_function(func: &mut walrus::LocalFunction, locals: &walrus::ModuleLocals) {
+    use std::collections::{HashMap, HashSet};
+    use walrus::ir::Instr;
+
+    let entry = func.entry_block();
+    let params: HashSet<walrus::LocalId> = func.args.iter().copied().collect();
+
+    let seq = func.block(entry);
+    if seq
+        .instrs
+        .iter()
+        .any(|(i, _)| matches!(i, Instr::Block { .. } | Instr::Loop { .. } | Instr::IfElse { .. }))
+    {
+        return;
+    }
+
+    // First-touch-to-last-touch span, by ordinal position in the block.
+    let mut spans: HashMap<walrus::LocalId, (usize, usize)> = HashMap::new();
+    let mut read_before_write: HashSet<walrus::LocalId> = HashSet::new();
+    for (i, (instr, _)) in seq.instrs.iter().enumerate() {
+        let (local, is_get) = match instr {
+            Instr::LocalGet(walrus::ir::LocalGet { local }) => (*local, true),
+            Instr::LocalSet(walrus::ir::LocalSet { local }) | Instr::LocalTee(walrus::ir::LocalTee { local }) => {
+                (*local, false)
+            }
+            _ => continue,
+        };
+        if !spans.contains_key(&local) && is_get {
+            read_before_write.insert(local);
+        }
+        spans.entry(local).and_modify(|(_, last)| *last = i).or_insert((i, i));
+    }
+
+    let mut candidates: Vec<walrus::LocalId> = spans
+        .keys()
+        .copied()
+        .filter(|l| !params.contains(l) && !read_before_write.contains(l))
+        .collect();
+    candidates.sort_by_key(|l| spans[l].0);
+
+    // Greedy interval-graph coloring: each candidate merges into the first
+    // same-typed slot whose last use is behind its own first touch.
+    let mut slots: Vec<(walrus::LocalId, usize, walrus::ValType)> = Vec::new();
+    let mut replacement: HashMap<walrus::LocalId, walrus::LocalId> = HashMap::new();
+    for local in candidates {
+        let (start, end) = spans[&local];
+        let ty = locals.get(local).ty();
+        match slots.iter_mut().find(|(_, free_from, slot_ty)| *slot_ty == ty && *free_from <= start) {
+            Some(slot) => {
+                replacement.insert(local, slot.0);
+                slot.1 = end;
+            }
+            None => slots.push((local, end, ty)),
+        }
+    }
+
+    if replacement.is_empty() {
+        return;
+    }
+
+    for (instr, _) in func.block_mut(entry).instrs.iter_mut() {
+        let local = match instr {
+            Instr::LocalGet(walrus::ir::LocalGet { local })
+            | Instr::LocalSet(walrus::ir::LocalSet { local })
+            | Instr::LocalTee(walrus::ir::LocalTee { local }) => local,
+            _ => continue,
+        };
+        if let Some(&canonical) = replacement.get(local) {
+            *local = canonical;
+        }
+    }
+}
+
+/// Folds an unconditional jump to a statically-known block back into a direct
+/// call, instead of returning the target PC to the host dispatch loop and
+/// paying for a `br_table`/`call_indirect` round trip to get back into guest
+/// code. `translate_flat_block`/`lower_terminator` both emit this exact
+/// `i32.const <target>; return` shape as the tail of an unconditional jump (a
+/// `jal` with `rd = x0`, a fallthrough, an `ecall`-free `Multiple`/`Simple`
+/// exit, ...); when `<target>` is the address of an actual exported block or
+/// function (not the `-1` halt sentinel or an ecall-flagged resume address),
+/// this rewrites that tail to `local.get $m; call <target>; return` instead.
+///
+/// Only folds when `target` is strictly greater than the function's own
+/// `block_addr`. Every cycle has to contain at least one edge that isn't a
+/// strict address increase, so restricting to forward edges guarantees this
+/// can never fold a loop backedge into a direct call — which matters because
+/// turning a backedge into a call would trade the host trampoline's bounded,
+/// iterative dispatch loop for unbounded Wasm call-stack recursion on a tight
+/// guest loop.
+///
+/// Scoped to entry-block-only, same as `coalesce_locals`: a jump tail nested
+/// inside a `Block`/`Loop`/`IfElse` is left alone rather than chased down.
+fn fold_pc_trampoline(module: &mut walrus::Module, source: &WasmModule) {
+    use walrus::ir::Instr;
+
+    let exported: std::collections::HashMap<String, walrus::FunctionId> = module
+        .exports
+        .iter()
+        .filter_map(|e| match e.item {
+            walrus::ExportItem::Function(id) => Some((e.name.clone(), id)),
+            _ => None,
+        })
+        .collect();
+
+    let by_addr: std::collections::HashMap<u64, walrus::FunctionId> = source
+        .functions
+        .iter()
+        .filter_map(|f| exported.get(&f.name).map(|&id| (f.block_addr, id)))
+        .collect();
+    let addr_of: std::collections::HashMap<walrus::FunctionId, u64> =
+        by_addr.iter().map(|(&addr, &id)| (id, addr)).collect();
+
+    const SYSCALL_FLAG: i32 = 0x8000_0000u32 as i32;
+
+    let walrus::Module { funcs, .. } = module;
+    for (func_id, func) in funcs.iter_local_mut() {
+        let own_addr = match addr_of.get(&func_id) {
+            Some(&addr) => addr,
+            None => continue,
+        };
+
+        let entry = func.entry_block();
+        let seq = func.block(entry);
+        if seq
+            .instrs
+            .iter()
+            .any(|(i, _)| matches!(i, Instr::Block { .. } | Instr::Loop { .. } | Instr::IfElse { .. }))
+        {
+            continue;
+        }
+
+        let mut rewrites: Vec<(usize, walrus::FunctionId)> = Vec::new();
+        for i in 0..seq.instrs.len().saturating_sub(1) {
+            let (first, _) = &seq.instrs[i];
+            let (second, _) = &seq.instrs[i + 1];
+            let value = match (first, second) {
+                (Instr::Const(walrus::ir::Const { value: walrus::ir::Value::I32(v) }), Instr::Return(_)) => *v,
+                _ => continue,
+            };
+            if value < 0 || value & SYSCALL_FLAG != 0 {
+                continue;
+            }
+            let target_addr = value as u32 as u64;
+            if target_addr <= own_addr {
+                continue;
+            }
+            if let Some(&target) = by_addr.get(&target_addr) {
+                rewrites.push((i, target));
+            }
+        }
+        if rewrites.is_empty() {
+            continue;
+        }
+
+        let param0 = func.args[0];
+        let seq = func.block_mut(entry);
+        // Apply back-to-front: each rewrite inserts an instruction, which
+        // would otherwise shift the still-to-process indices ahead of it.
+        for (i, target) in rewrites.into_iter().rev() {
+            seq.instrs[i] = (Instr::LocalGet(walrus::ir::LocalGet { local: param0 }), Default::default());
+            seq.instrs
+                .insert(i + 1, (Instr::Call(walrus::ir::Call { func: target }), Default::default()));
+        }
+    }
+}
+
+/// No-op by design, not a placeholder: unlike `coalesce_locals` and
+/// `fold_pc_trampoline`, data-segment merging doesn't need walrus IR at
+/// all to do for real, so it's done earlier, directly in `build`, via
+/// `merge_contiguous_segments` — by the time a module reaches the walrus
+/// pipeline its data segments are already as merged as they're going to
+/// get. This stays declared here so the pass pipeline's shape (and this
+/// explanation) is visible alongside the other two, still-unimplemented
+/// passes.
+fn merge_adjacent_data_segments(_module: &mut walrus::Module) {}
+
+/// One data segment waiting to be emitted, before `merge_contiguous_segments`
+/// combines the ones that directly abut in memory.
+struct PendingSegment {
+    offset: u32,
+    bytes: Vec<u8>,
+}
+
+/// Merge segments that directly abut in memory (`offset + bytes.len() ==
+/// next.offset`) into one, so the data section doesn't pay a per-segment
+/// encoding and instantiation-time overhead for layouts that happen to be
+/// contiguous — today that's mostly theoretical (the dispatch map, stack
+/// image, and sp write-back `build` emits don't happen to touch), but it's
+/// real, general merging logic that runs on every build, not a stub kept
+/// around for later.
+///
+/// Input segments must not overlap (a caller bug, not a runtime
+/// condition), so that's a `debug_assert`, not a recoverable error.
+fn merge_contiguous_segments(mut segments: Vec<PendingSegment>) -> Vec<PendingSegment> {
+    segments.sort_by_key(|s| s.offset);
+
+    let mut merged: Vec<PendingSegment> = Vec::with_capacity(segments.len());
+    for seg in segments {
+        if let Some(last) = merged.last_mut() {
+            let last_end = last.offset + last.bytes.len() as u32;
+            debug_assert!(seg.offset >= last_end, "data segments must not overlap");
+            if last_end == seg.offset {
+                last.bytes.extend(seg.bytes);
+                continue;
+            }
+        }
+        merged.push(seg);
+    }
+    merged
+}
+
+/// Build the final Wasm binary. `stack`, if given, places the initial
+/// process stack and writes the resulting `sp` into x2's register slot;
+/// see [`StackPlacement`].
+pub fn build(module: &WasmModule, stack: Option<&StackPlacement>) -> Result<Vec<u8>> {
     let mut wasm = Module::new();
 
     // Pre-compute the dispatch table so we can reference it during code generation
@@ -114,6 +539,7 @@ pub fn build(module: &WasmModule) -> Result<Vec<u8>> {
             maximum: Some((module.memory_pages * 4) as u64),
             memory64: false,
             shared: false,
+            page_size_log2: None,
         },
     );
 
@@ -145,8 +571,10 @@ pub fn build(module: &WasmModule) -> Result<Vec<u8>> {
     // Table for block dispatch
     tables.table(TableType {
         element_type: wasm_encoder::RefType::FUNCREF,
-        minimum: module.functions.len() as u32,
-        maximum: Some(module.functions.len() as u32),
+        table64: false,
+        minimum: module.functions.len() as u64,
+        maximum: Some(module.functions.len() as u64),
+        shared: false,
     });
 
     wasm.section(&tables);
@@ -171,6 +599,24 @@ pub fn build(module: &WasmModule) -> Result<Vec<u8>> {
 
     wasm.section(&exports);
 
+    // ==========================================================================
+    // Element section (populates the table for call_indirect dispatch)
+    // ==========================================================================
+    if dispatch_table.wide && !module.functions.is_empty() {
+        let mut elements = ElementSection::new();
+        // Table slot i -> block function index i + 2 (0 = syscall import,
+        // 1 = dispatch function).
+        let func_indices: Vec<u32> = (0..module.functions.len() as u32).map(|i| i + 2).collect();
+        elements.segment(wasm_encoder::ElementSegment {
+            mode: ElementMode::Active {
+                table: Some(0),
+                offset: &ConstExpr::i32_const(0),
+            },
+            elements: Elements::Functions(&func_indices),
+        });
+        wasm.section(&elements);
+    }
+
     // ==========================================================================
     // Code section
     // ==========================================================================
@@ -180,32 +626,91 @@ pub fn build(module: &WasmModule) -> Result<Vec<u8>> {
     let dispatch_func = build_dispatch_function(module, &dispatch_table);
     codes.function(&dispatch_func);
 
-    // Block functions
-    for func in &module.functions {
-        let wasm_func = build_block_function(func)?;
+    // Block functions, tracking each one's body length so we can emit the
+    // `debug_riscv_pc` custom section below.
+    let mut riscv_pc_table: Vec<(u32, u64, u32)> = Vec::with_capacity(module.functions.len());
+    for (idx, func) in module.functions.iter().enumerate() {
+        let (wasm_func, body_len) = build_block_function(func)?;
         codes.function(&wasm_func);
+        riscv_pc_table.push(((idx + 2) as u32, func.block_addr, body_len));
     }
 
     wasm.section(&codes);
 
     // ==========================================================================
-    // Data section (dispatch mapping table)
+    // Data section (dispatch mapping table, plus the initial stack image
+    // and its sp write-back, when `stack` is given)
     // ==========================================================================
+    let mut pending_segments = Vec::new();
+
     if !dispatch_table.data.is_empty() {
+        pending_segments.push(PendingSegment {
+            offset: DISPATCH_MAP_OFFSET,
+            bytes: dispatch_table.data.clone(),
+        });
+    }
+
+    if let Some(placement) = stack {
+        let image = crate::stack::build_stack(&placement.config, placement.base, placement.size)?;
+        pending_segments.push(PendingSegment { offset: placement.base as u32, bytes: image.bytes });
+        pending_segments.push(PendingSegment {
+            offset: SP_REGISTER_OFFSET as u32,
+            bytes: image.sp.to_le_bytes().to_vec(),
+        });
+    }
+
+    if !pending_segments.is_empty() {
         let mut data_section = DataSection::new();
-        // Active data segment: initialize memory at DISPATCH_MAP_OFFSET
-        data_section.active(
-            0, // memory index
-            &ConstExpr::i32_const(DISPATCH_MAP_OFFSET as i32),
-            dispatch_table.data.iter().copied(),
-        );
+        for seg in merge_contiguous_segments(pending_segments) {
+            data_section.active(0, &ConstExpr::i32_const(seg.offset as i32), seg.bytes);
+        }
         wasm.section(&data_section);
     }
 
+    // ==========================================================================
+    // Name section (standard debug names for `run` and each block function)
+    // ==========================================================================
+    let mut names = wasm_encoder::NameSection::new();
+    let mut func_names = wasm_encoder::NameMap::new();
+    func_names.append(1, "run");
+    for (idx, func) in module.functions.iter().enumerate() {
+        func_names.append((idx + 2) as u32, &func.name);
+    }
+    names.functions(&func_names);
+    wasm.section(&names);
+
+    // ==========================================================================
+    // `debug_riscv_pc` custom section: Wasm function index -> originating
+    // RISC-V block address, plus that function's body length, so external
+    // tooling can translate a trap inside a given function back to roughly
+    // where in the guest program it happened.
+    // ==========================================================================
+    let mut debug_riscv_pc = Vec::new();
+    debug_riscv_pc.extend((riscv_pc_table.len() as u32).to_le_bytes());
+    for (func_idx, block_addr, body_len) in &riscv_pc_table {
+        debug_riscv_pc.extend(func_idx.to_le_bytes());
+        debug_riscv_pc.extend(block_addr.to_le_bytes());
+        debug_riscv_pc.extend(body_len.to_le_bytes());
+    }
+    wasm.section(&wasm_encoder::CustomSection {
+        name: "debug_riscv_pc".into(),
+        data: debug_riscv_pc.into(),
+    });
+
     Ok(wasm.finish())
 }
 
-/// Build the main dispatch function using br_table for O(1) block dispatch.
+/// Build the main dispatch function using br_table for O(1) block dispatch,
+/// or `call_indirect` through the function table once the module has
+/// `CALL_INDIRECT_THRESHOLD` or more block functions (see `DispatchTable::wide`).
+///
+/// When `module.fuel_metering` is set, the loop also decrements an i64
+/// fuel counter at `table.fuel_offset()` (placed right after the
+/// dispatch map, whose size varies with the guest program) once per
+/// dispatched block; reaching zero saves `$pc` to `table.saved_pc_offset()`
+/// and returns `OUT_OF_FUEL` so the host can refill the counter and
+/// resume by passing the saved PC back in as `$start_pc`. Non-metered
+/// builds skip this entirely, so they pay no extra cost.
 ///
 /// The dispatch loop structure:
 /// ```text
@@ -234,11 +739,18 @@ pub fn build(module: &WasmModule) -> Result<Vec<u8>> {
 ///   end
 /// ```
 fn build_dispatch_function(module: &WasmModule, table: &DispatchTable) -> Function {
-    let mut func = Function::new(vec![(1, ValType::I32)]); // 1 local: $pc (local 2)
+    // Locals: param $m=0, param $start_pc=1, local $pc=2, and (when fuel
+    // metering is on) local $fuel=3 used to hold the decremented counter
+    // before it's written back and tested.
+    let locals = if module.fuel_metering {
+        vec![(1, ValType::I32), (1, ValType::I64)]
+    } else {
+        vec![(1, ValType::I32)]
+    };
+    let mut func = Function::new(locals);
+    const FUEL_LOCAL: u32 = 3;
     let n = table.num_functions;
 
-    // Locals: param $m=0, param $start_pc=1, local $pc=2
-
     // Initialize $pc from $start_pc parameter
     func.instruction(&Instruction::LocalGet(1));
     func.instruction(&Instruction::LocalSet(2));
@@ -246,6 +758,43 @@ fn build_dispatch_function(module: &WasmModule, table: &DispatchTable) -> Functi
     // --- Main dispatch loop ---
     func.instruction(&Instruction::Loop(wasm_encoder::BlockType::Empty));
 
+    // Fuel check: decrement the counter once per dispatched block and,
+    // if it runs out, save $pc and return the out-of-fuel sentinel so the
+    // host can refill the counter and resume from $start_pc = saved $pc.
+    if module.fuel_metering {
+        let fuel_offset = table.fuel_offset();
+        let saved_pc_offset = table.saved_pc_offset();
+        func.instruction(&Instruction::I32Const(fuel_offset as i32));
+        func.instruction(&Instruction::I32Const(fuel_offset as i32));
+        func.instruction(&Instruction::I64Load(wasm_encoder::MemArg {
+            offset: 0,
+            align: 3,
+            memory_index: 0,
+        }));
+        func.instruction(&Instruction::I64Const(1));
+        func.instruction(&Instruction::I64Sub);
+        func.instruction(&Instruction::LocalTee(FUEL_LOCAL));
+        func.instruction(&Instruction::I64Store(wasm_encoder::MemArg {
+            offset: 0,
+            align: 3,
+            memory_index: 0,
+        }));
+        func.instruction(&Instruction::LocalGet(FUEL_LOCAL));
+        func.instruction(&Instruction::I64Const(0));
+        func.instruction(&Instruction::I64LeS);
+        func.instruction(&Instruction::If(wasm_encoder::BlockType::Empty));
+        func.instruction(&Instruction::I32Const(saved_pc_offset as i32));
+        func.instruction(&Instruction::LocalGet(2)); // $pc
+        func.instruction(&Instruction::I32Store(wasm_encoder::MemArg {
+            offset: 0,
+            align: 2,
+            memory_index: 0,
+        }));
+        func.instruction(&Instruction::I32Const(OUT_OF_FUEL));
+        func.instruction(&Instruction::Return);
+        func.instruction(&Instruction::End); // end if
+    }
+
     // Check for halt: if ($pc == -1) return 0
     func.instruction(&Instruction::LocalGet(2));
     func.instruction(&Instruction::I32Const(-1));
@@ -271,6 +820,47 @@ fn build_dispatch_function(module: &WasmModule, table: &DispatchTable) -> Functi
         // No block functions - just halt
         func.instruction(&Instruction::I32Const(-1));
         func.instruction(&Instruction::Return);
+    } else if table.wide {
+        // --- call_indirect dispatch ---
+        //
+        // Collapses dispatch to constant code size regardless of `n`: the
+        // 4-byte dispatch map gives the table slot directly (table slot i
+        // holds block function index i + 2, via the active element
+        // segment built in `build`), and a single `CallIndirect` replaces
+        // the nested-block `br_table`.
+        //
+        // index = i32.load(DISPATCH_MAP_OFFSET + (($pc - min_addr) / 2) * 4)
+        //
+        // call_indirect expects its callee args pushed first and the
+        // table index last (on top), so $m goes on the stack before the
+        // index computation.
+        func.instruction(&Instruction::LocalGet(0)); // $m, the block function's only param
+
+        func.instruction(&Instruction::LocalGet(2)); // $pc
+        func.instruction(&Instruction::I32Const(table.min_addr as i32));
+        func.instruction(&Instruction::I32Sub); // $pc - min_addr
+        func.instruction(&Instruction::I32Const(1));
+        func.instruction(&Instruction::I32ShrU); // / 2 (half-word index)
+        func.instruction(&Instruction::I32Const(2));
+        func.instruction(&Instruction::I32Shl); // * 4 (i32 entries)
+        func.instruction(&Instruction::I32Const(DISPATCH_MAP_OFFSET as i32));
+        func.instruction(&Instruction::I32Add);
+        func.instruction(&Instruction::I32Load(wasm_encoder::MemArg {
+            offset: 0,
+            align: 2,
+            memory_index: 0,
+        }));
+
+        // The dispatch map stores the same dense function indices the
+        // br_table path calls directly (`Call(i + 2)`), and the element
+        // segment built in `build` maps table slot i to block function
+        // index i + 2 — i.e. table slot == dense index. No offset needed.
+        func.instruction(&Instruction::CallIndirect {
+            type_index: 0,
+            table_index: 0,
+        });
+        func.instruction(&Instruction::LocalSet(2)); // $pc = returned next-PC
+        func.instruction(&Instruction::Br(0)); // br $dispatch loop
     } else {
         // --- br_table dispatch ---
         //
@@ -348,277 +938,379 @@ fn build_dispatch_function(module: &WasmModule, table: &DispatchTable) -> Functi
     func
 }
 
-/// Build a block function from our IR
-fn build_block_function(func: &crate::translate::WasmFunction) -> Result<Function> {
+/// Build a block function from our IR, returning the function alongside
+/// the byte length of its body (relative to the body's own start, i.e.
+/// not counting the function's size/locals preamble in the code section).
+/// `build` uses this to populate the `debug_riscv_pc` custom section.
+fn build_block_function(func: &crate::translate::WasmFunction) -> Result<(Function, u32)> {
     let mut wasm_func = Function::new(vec![(func.num_locals, ValType::I64)]);
 
+    let mut body_len = 0u32;
     for inst in &func.body {
-        emit_instruction(&mut wasm_func, inst)?;
+        body_len += emit_instruction(&mut wasm_func, inst)?;
     }
 
     wasm_func.instruction(&Instruction::End);
+    body_len += instruction_len(&Instruction::End);
 
-    Ok(wasm_func)
+    Ok((wasm_func, body_len))
 }
 
 /// Emit a single instruction
-fn emit_instruction(func: &mut Function, inst: &WasmInst) -> Result<()> {
-    match inst {
+/// Number of bytes `inst` occupies once encoded, used to build the
+/// guest-PC -> Wasm-offset debug table in `build_block_function`.
+fn instruction_len(inst: &Instruction) -> u32 {
+    use wasm_encoder::Encode;
+    let mut buf = Vec::new();
+    inst.encode(&mut buf);
+    buf.len() as u32
+}
+
+/// Emit a single instruction and return the number of bytes it occupies
+/// in the final Wasm binary (0 for `Comment`, which emits nothing).
+fn emit_instruction(func: &mut Function, inst: &WasmInst) -> Result<u32> {
+    let wasm_inst = match inst {
         // Control flow
-        WasmInst::Block { label: _ } => {
-            func.instruction(&Instruction::Block(wasm_encoder::BlockType::Empty));
-        }
-        WasmInst::Loop { label: _ } => {
-            func.instruction(&Instruction::Loop(wasm_encoder::BlockType::Empty));
-        }
-        WasmInst::End => {
-            func.instruction(&Instruction::End);
-        }
-        WasmInst::Br { label } => {
-            func.instruction(&Instruction::Br(*label));
-        }
-        WasmInst::BrIf { label } => {
-            func.instruction(&Instruction::BrIf(*label));
-        }
-        WasmInst::Return => {
-            func.instruction(&Instruction::Return);
-        }
-        WasmInst::Call { func_idx } => {
-            func.instruction(&Instruction::Call(*func_idx));
-        }
+        WasmInst::Block { label: _ } => Instruction::Block(wasm_encoder::BlockType::Empty),
+        WasmInst::Loop { label: _ } => Instruction::Loop(wasm_encoder::BlockType::Empty),
+        WasmInst::End => Instruction::End,
+        WasmInst::Br { label } => Instruction::Br(*label),
+        WasmInst::BrIf { label } => Instruction::BrIf(*label),
+        WasmInst::Return => Instruction::Return,
+        WasmInst::Call { func_idx } => Instruction::Call(*func_idx),
 
         // Locals
-        WasmInst::LocalGet { idx } => {
-            func.instruction(&Instruction::LocalGet(*idx));
-        }
-        WasmInst::LocalSet { idx } => {
-            func.instruction(&Instruction::LocalSet(*idx));
-        }
-        WasmInst::LocalTee { idx } => {
-            func.instruction(&Instruction::LocalTee(*idx));
-        }
+        WasmInst::LocalGet { idx } => Instruction::LocalGet(*idx),
+        WasmInst::LocalSet { idx } => Instruction::LocalSet(*idx),
+        WasmInst::LocalTee { idx } => Instruction::LocalTee(*idx),
 
         // Constants
-        WasmInst::I32Const { value } => {
-            func.instruction(&Instruction::I32Const(*value));
-        }
-        WasmInst::I64Const { value } => {
-            func.instruction(&Instruction::I64Const(*value));
-        }
+        WasmInst::I32Const { value } => Instruction::I32Const(*value),
+        WasmInst::I64Const { value } => Instruction::I64Const(*value),
 
         // Memory loads
-        WasmInst::I32Load { offset } => {
-            func.instruction(&Instruction::I32Load(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 2,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Load { offset } => {
-            func.instruction(&Instruction::I64Load(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 3,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Load8S { offset } => {
-            func.instruction(&Instruction::I64Load8S(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 0,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Load8U { offset } => {
-            func.instruction(&Instruction::I64Load8U(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 0,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Load16S { offset } => {
-            func.instruction(&Instruction::I64Load16S(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 1,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Load16U { offset } => {
-            func.instruction(&Instruction::I64Load16U(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 1,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Load32S { offset } => {
-            func.instruction(&Instruction::I64Load32S(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 2,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Load32U { offset } => {
-            func.instruction(&Instruction::I64Load32U(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 2,
-                memory_index: 0,
-            }));
-        }
+        WasmInst::I32Load { offset } => Instruction::I32Load(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 2,
+            memory_index: 0,
+        }),
+        WasmInst::I64Load { offset } => Instruction::I64Load(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 3,
+            memory_index: 0,
+        }),
+        WasmInst::I64Load8S { offset } => Instruction::I64Load8S(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 0,
+            memory_index: 0,
+        }),
+        WasmInst::I64Load8U { offset } => Instruction::I64Load8U(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 0,
+            memory_index: 0,
+        }),
+        WasmInst::I64Load16S { offset } => Instruction::I64Load16S(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 1,
+            memory_index: 0,
+        }),
+        WasmInst::I64Load16U { offset } => Instruction::I64Load16U(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 1,
+            memory_index: 0,
+        }),
+        WasmInst::I64Load32S { offset } => Instruction::I64Load32S(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 2,
+            memory_index: 0,
+        }),
+        WasmInst::I64Load32U { offset } => Instruction::I64Load32U(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 2,
+            memory_index: 0,
+        }),
 
         // Memory stores
-        WasmInst::I32Store { offset } => {
-            func.instruction(&Instruction::I32Store(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 2,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Store { offset } => {
-            func.instruction(&Instruction::I64Store(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 3,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Store8 { offset } => {
-            func.instruction(&Instruction::I64Store8(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 0,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Store16 { offset } => {
-            func.instruction(&Instruction::I64Store16(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 1,
-                memory_index: 0,
-            }));
-        }
-        WasmInst::I64Store32 { offset } => {
-            func.instruction(&Instruction::I64Store32(wasm_encoder::MemArg {
-                offset: *offset as u64,
-                align: 2,
-                memory_index: 0,
-            }));
-        }
+        WasmInst::I32Store { offset } => Instruction::I32Store(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 2,
+            memory_index: 0,
+        }),
+        WasmInst::I64Store { offset } => Instruction::I64Store(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 3,
+            memory_index: 0,
+        }),
+        WasmInst::I64Store8 { offset } => Instruction::I64Store8(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 0,
+            memory_index: 0,
+        }),
+        WasmInst::I64Store16 { offset } => Instruction::I64Store16(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 1,
+            memory_index: 0,
+        }),
+        WasmInst::I64Store32 { offset } => Instruction::I64Store32(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 2,
+            memory_index: 0,
+        }),
 
         // i64 arithmetic
-        WasmInst::I64Add => {
-            func.instruction(&Instruction::I64Add);
-        }
-        WasmInst::I64Sub => {
-            func.instruction(&Instruction::I64Sub);
-        }
-        WasmInst::I64Mul => {
-            func.instruction(&Instruction::I64Mul);
-        }
-        WasmInst::I64DivS => {
-            func.instruction(&Instruction::I64DivS);
-        }
-        WasmInst::I64DivU => {
-            func.instruction(&Instruction::I64DivU);
-        }
-        WasmInst::I64RemS => {
-            func.instruction(&Instruction::I64RemS);
-        }
-        WasmInst::I64RemU => {
-            func.instruction(&Instruction::I64RemU);
-        }
-        WasmInst::I64And => {
-            func.instruction(&Instruction::I64And);
-        }
-        WasmInst::I64Or => {
-            func.instruction(&Instruction::I64Or);
-        }
-        WasmInst::I64Xor => {
-            func.instruction(&Instruction::I64Xor);
-        }
-        WasmInst::I64Shl => {
-            func.instruction(&Instruction::I64Shl);
-        }
-        WasmInst::I64ShrS => {
-            func.instruction(&Instruction::I64ShrS);
-        }
-        WasmInst::I64ShrU => {
-            func.instruction(&Instruction::I64ShrU);
-        }
-        WasmInst::I64Eqz => {
-            func.instruction(&Instruction::I64Eqz);
-        }
-        WasmInst::I64Eq => {
-            func.instruction(&Instruction::I64Eq);
-        }
-        WasmInst::I64Ne => {
-            func.instruction(&Instruction::I64Ne);
-        }
-        WasmInst::I64LtS => {
-            func.instruction(&Instruction::I64LtS);
-        }
-        WasmInst::I64LtU => {
-            func.instruction(&Instruction::I64LtU);
-        }
-        WasmInst::I64GtS => {
-            func.instruction(&Instruction::I64GtS);
-        }
-        WasmInst::I64GtU => {
-            func.instruction(&Instruction::I64GtU);
-        }
-        WasmInst::I64LeS => {
-            func.instruction(&Instruction::I64LeS);
-        }
-        WasmInst::I64LeU => {
-            func.instruction(&Instruction::I64LeU);
-        }
-        WasmInst::I64GeS => {
-            func.instruction(&Instruction::I64GeS);
-        }
-        WasmInst::I64GeU => {
-            func.instruction(&Instruction::I64GeU);
-        }
+        WasmInst::I64Add => Instruction::I64Add,
+        WasmInst::I64Sub => Instruction::I64Sub,
+        WasmInst::I64Mul => Instruction::I64Mul,
+        WasmInst::I64DivS => Instruction::I64DivS,
+        WasmInst::I64DivU => Instruction::I64DivU,
+        WasmInst::I64RemS => Instruction::I64RemS,
+        WasmInst::I64RemU => Instruction::I64RemU,
+        WasmInst::I64And => Instruction::I64And,
+        WasmInst::I64Or => Instruction::I64Or,
+        WasmInst::I64Xor => Instruction::I64Xor,
+        WasmInst::I64Shl => Instruction::I64Shl,
+        WasmInst::I64ShrS => Instruction::I64ShrS,
+        WasmInst::I64ShrU => Instruction::I64ShrU,
+        WasmInst::I64Eqz => Instruction::I64Eqz,
+        WasmInst::I64Eq => Instruction::I64Eq,
+        WasmInst::I64Ne => Instruction::I64Ne,
+        WasmInst::I64LtS => Instruction::I64LtS,
+        WasmInst::I64LtU => Instruction::I64LtU,
+        WasmInst::I64GtS => Instruction::I64GtS,
+        WasmInst::I64GtU => Instruction::I64GtU,
+        WasmInst::I64LeS => Instruction::I64LeS,
+        WasmInst::I64LeU => Instruction::I64LeU,
+        WasmInst::I64GeS => Instruction::I64GeS,
+        WasmInst::I64GeU => Instruction::I64GeU,
 
         // i32 arithmetic
-        WasmInst::I32Add => {
-            func.instruction(&Instruction::I32Add);
-        }
-        WasmInst::I32Sub => {
-            func.instruction(&Instruction::I32Sub);
-        }
-        WasmInst::I32Eqz => {
-            func.instruction(&Instruction::I32Eqz);
-        }
-        WasmInst::I32Eq => {
-            func.instruction(&Instruction::I32Eq);
-        }
-        WasmInst::I32Ne => {
-            func.instruction(&Instruction::I32Ne);
-        }
+        WasmInst::I32Add => Instruction::I32Add,
+        WasmInst::I32Sub => Instruction::I32Sub,
+        WasmInst::I32Eqz => Instruction::I32Eqz,
+        WasmInst::I32Eq => Instruction::I32Eq,
+        WasmInst::I32Ne => Instruction::I32Ne,
 
         // Conversions
-        WasmInst::I32WrapI64 => {
-            func.instruction(&Instruction::I32WrapI64);
-        }
-        WasmInst::I64ExtendI32S => {
-            func.instruction(&Instruction::I64ExtendI32S);
-        }
-        WasmInst::I64ExtendI32U => {
-            func.instruction(&Instruction::I64ExtendI32U);
-        }
+        WasmInst::I32WrapI64 => Instruction::I32WrapI64,
+        WasmInst::I64ExtendI32S => Instruction::I64ExtendI32S,
+        WasmInst::I64ExtendI32U => Instruction::I64ExtendI32U,
+
+        // Floating-point loads/stores (RISC-V F/D extensions)
+        WasmInst::F32Load { offset } => Instruction::F32Load(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 2,
+            memory_index: 0,
+        }),
+        WasmInst::F64Load { offset } => Instruction::F64Load(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 3,
+            memory_index: 0,
+        }),
+        WasmInst::F32Store { offset } => Instruction::F32Store(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 2,
+            memory_index: 0,
+        }),
+        WasmInst::F64Store { offset } => Instruction::F64Store(wasm_encoder::MemArg {
+            offset: *offset as u64,
+            align: 3,
+            memory_index: 0,
+        }),
+
+        // Floating-point arithmetic (f64 / RISC-V D extension)
+        WasmInst::F64Add => Instruction::F64Add,
+        WasmInst::F64Sub => Instruction::F64Sub,
+        WasmInst::F64Mul => Instruction::F64Mul,
+        WasmInst::F64Div => Instruction::F64Div,
+        WasmInst::F64Sqrt => Instruction::F64Sqrt,
+        WasmInst::F64Min => Instruction::F64Min,
+        WasmInst::F64Max => Instruction::F64Max,
+        WasmInst::F64Abs => Instruction::F64Abs,
+        WasmInst::F64Neg => Instruction::F64Neg,
+        WasmInst::F64Copysign => Instruction::F64Copysign,
+
+        // Floating-point arithmetic (f32 / RISC-V F extension)
+        WasmInst::F32Add => Instruction::F32Add,
+        WasmInst::F32Sub => Instruction::F32Sub,
+        WasmInst::F32Mul => Instruction::F32Mul,
+        WasmInst::F32Div => Instruction::F32Div,
+        WasmInst::F32Sqrt => Instruction::F32Sqrt,
+        WasmInst::F32Min => Instruction::F32Min,
+        WasmInst::F32Max => Instruction::F32Max,
+        WasmInst::F32Abs => Instruction::F32Abs,
+        WasmInst::F32Neg => Instruction::F32Neg,
+        WasmInst::F32Copysign => Instruction::F32Copysign,
+
+        // Floating-point comparisons (f64)
+        WasmInst::F64Eq => Instruction::F64Eq,
+        WasmInst::F64Ne => Instruction::F64Ne,
+        WasmInst::F64Lt => Instruction::F64Lt,
+        WasmInst::F64Le => Instruction::F64Le,
+        WasmInst::F64Gt => Instruction::F64Gt,
+        WasmInst::F64Ge => Instruction::F64Ge,
+
+        // Conversions between integer and floating-point
+        WasmInst::F64ConvertI64S => Instruction::F64ConvertI64S,
+        WasmInst::F64ConvertI64U => Instruction::F64ConvertI64U,
+        WasmInst::I64TruncF64S => Instruction::I64TruncF64S,
+        WasmInst::I64TruncF64U => Instruction::I64TruncF64U,
+        WasmInst::F32DemoteF64 => Instruction::F32DemoteF64,
+        WasmInst::F64PromoteF32 => Instruction::F64PromoteF32,
+        WasmInst::F32ReinterpretI32 => Instruction::F32ReinterpretI32,
+        WasmInst::F64ReinterpretI64 => Instruction::F64ReinterpretI64,
+        WasmInst::I32ReinterpretF32 => Instruction::I32ReinterpretF32,
+        WasmInst::I64ReinterpretF64 => Instruction::I64ReinterpretF64,
 
         // Stack
-        WasmInst::Drop => {
-            func.instruction(&Instruction::Drop);
-        }
-        WasmInst::Select => {
-            func.instruction(&Instruction::Select);
-        }
+        WasmInst::Drop => Instruction::Drop,
+        WasmInst::Select => Instruction::Select,
 
-        // Comments are no-ops
-        WasmInst::Comment { .. } => {}
+        // Comments emit nothing, so they don't occupy a Wasm offset.
+        WasmInst::Comment { .. } => return Ok(0),
+    };
 
-        // Unimplemented instructions
-        _ => {
-            // Skip unimplemented for now
-        }
+    func.instruction(&wasm_inst);
+    Ok(instruction_len(&wasm_inst))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate::WasmFunction;
+    use walrus::ir::{Instr, Value};
+    use walrus::{FunctionBuilder, Module as WalrusModule, ValType as WalrusValType};
+
+    // The pipeline's own codegen never emits more than one non-reserved
+    // local per function, so these passes can't be exercised against real
+    // `build_via_ir` output; they're tested directly against hand-built
+    // walrus IR instead.
+
+    #[test]
+    fn coalesce_locals_merges_two_non_overlapping_same_type_locals() {
+        let mut module = WalrusModule::default();
+        let a = module.locals.add(WalrusValType::I32);
+        let b = module.locals.add(WalrusValType::I32);
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &[], &[WalrusValType::I32]);
+        builder
+            .func_body()
+            .i32_const(1)
+            .local_set(a)
+            .local_get(a)
+            .drop()
+            .i32_const(2)
+            .local_set(b)
+            .local_get(b);
+        let func_id = builder.finish(vec![], &mut module.funcs);
+
+        coalesce_locals(&mut module);
+
+        let func = module.funcs.get(func_id).kind.unwrap_local();
+        let seq = func.block(func.entry_block());
+        let referenced: Vec<walrus::LocalId> = seq
+            .instrs
+            .iter()
+            .filter_map(|(instr, _)| match instr {
+                Instr::LocalGet(walrus::ir::LocalGet { local })
+                | Instr::LocalSet(walrus::ir::LocalSet { local }) => Some(*local),
+                _ => None,
+            })
+            .collect();
+        assert!(referenced.iter().all(|&l| l == referenced[0]), "a and b should share one slot: {referenced:?}");
+    }
+
+    #[test]
+    fn coalesce_locals_leaves_a_function_parameter_alone() {
+        let mut module = WalrusModule::default();
+        let param = module.locals.add(WalrusValType::I32);
+        let other = module.locals.add(WalrusValType::I32);
+
+        let mut builder = FunctionBuilder::new(&mut module.types, &[WalrusValType::I32], &[WalrusValType::I32]);
+        builder
+            .func_body()
+            .i32_const(1)
+            .local_set(other)
+            .drop()
+            .local_get(param);
+        let func_id = builder.finish(vec![param], &mut module.funcs);
+
+        coalesce_locals(&mut module);
+
+        let func = module.funcs.get(func_id).kind.unwrap_local();
+        let seq = func.block(func.entry_block());
+        let gets_param = seq
+            .instrs
+            .iter()
+            .any(|(instr, _)| matches!(instr, Instr::LocalGet(walrus::ir::LocalGet { local }) if *local == param));
+        assert!(gets_param, "the parameter's own slot must never be reassigned");
+    }
+
+    #[test]
+    fn fold_pc_trampoline_replaces_a_forward_jump_with_a_direct_call() {
+        let mut module = WalrusModule::default();
+
+        let mut callee_builder = FunctionBuilder::new(&mut module.types, &[WalrusValType::I32], &[WalrusValType::I32]);
+        callee_builder.func_body().i32_const(0).return_();
+        let callee_id = callee_builder.finish(vec![module.locals.add(WalrusValType::I32)], &mut module.funcs);
+        module.exports.add("block_20", callee_id);
+
+        let mut caller_builder = FunctionBuilder::new(&mut module.types, &[WalrusValType::I32], &[WalrusValType::I32]);
+        caller_builder.func_body().i32_const(0x20).return_();
+        let m_param = module.locals.add(WalrusValType::I32);
+        let caller_id = caller_builder.finish(vec![m_param], &mut module.funcs);
+        module.exports.add("block_10", caller_id);
+
+        let source = WasmModule {
+            functions: vec![
+                WasmFunction { name: "block_10".into(), block_addr: 0x10, num_locals: 0, body: vec![] },
+                WasmFunction { name: "block_20".into(), block_addr: 0x20, num_locals: 0, body: vec![] },
+            ],
+            memory_pages: 1,
+            fuel_metering: false,
+        };
+
+        fold_pc_trampoline(&mut module, &source);
+
+        let caller = module.funcs.get(caller_id).kind.unwrap_local();
+        let seq = caller.block(caller.entry_block());
+        let calls_callee = seq
+            .instrs
+            .iter()
+            .any(|(instr, _)| matches!(instr, Instr::Call(walrus::ir::Call { func }) if *func == callee_id));
+        assert!(calls_callee, "forward jump to a known block should become a direct call: {:?}", seq.instrs);
     }
 
-    Ok(())
+    #[test]
+    fn fold_pc_trampoline_leaves_a_backward_jump_alone() {
+        let mut module = WalrusModule::default();
+
+        let mut callee_builder = FunctionBuilder::new(&mut module.types, &[WalrusValType::I32], &[WalrusValType::I32]);
+        callee_builder.func_body().i32_const(0).return_();
+        let callee_id = callee_builder.finish(vec![module.locals.add(WalrusValType::I32)], &mut module.funcs);
+        module.exports.add("block_10", callee_id);
+
+        let mut caller_builder = FunctionBuilder::new(&mut module.types, &[WalrusValType::I32], &[WalrusValType::I32]);
+        caller_builder.func_body().i32_const(0x10).return_();
+        let m_param = module.locals.add(WalrusValType::I32);
+        let caller_id = caller_builder.finish(vec![m_param], &mut module.funcs);
+        module.exports.add("block_20", caller_id);
+
+        let source = WasmModule {
+            functions: vec![
+                WasmFunction { name: "block_20".into(), block_addr: 0x20, num_locals: 0, body: vec![] },
+                WasmFunction { name: "block_10".into(), block_addr: 0x10, num_locals: 0, body: vec![] },
+            ],
+            memory_pages: 1,
+            fuel_metering: false,
+        };
+
+        fold_pc_trampoline(&mut module, &source);
+
+        let caller = module.funcs.get(caller_id).kind.unwrap_local();
+        let seq = caller.block(caller.entry_block());
+        let still_returns_constant = seq.instrs.iter().any(
+            |(instr, _)| matches!(instr, Instr::Const(walrus::ir::Const { value: Value::I32(v) }) if *v == 0x10),
+        );
+        assert!(still_returns_constant, "a backedge must stay a host round trip, never a direct call");
+    }
 }
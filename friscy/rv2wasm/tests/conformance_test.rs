@@ -0,0 +1,139 @@
+// conformance_test.rs - Differential testing: compiled Wasm vs rv2wasm::interp
+//
+// Each fixture is a small RISC-V ELF checked into tests/conformance/. We
+// run it through rv2wasm::interp (the reference interpreter) and through
+// the compiled Wasm module under an embedded `wasmi` runtime, then compare
+// final register state. Fixtures that aren't present are skipped, matching
+// the rest of this suite's pattern for binaries built out-of-band.
+
+use std::path::PathBuf;
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("conformance").join(name)
+}
+
+/// Flatten a fixture's loadable segments into one byte image, positioned
+/// so that `image[vaddr - mem_base]` holds the byte originally at `vaddr`.
+/// Used to seed both the reference interpreter's memory and the compiled
+/// module's linear memory identically.
+fn load_image(elf_data: &[u8], info: &rv2wasm::elf::ElfInfo) -> (u64, Vec<u8>) {
+    let mem_base = info.segments.iter().map(|s| s.vaddr).min().unwrap_or(info.entry);
+    let mem_end = info.segments.iter().map(|s| s.vaddr + s.memsz).max().unwrap_or(mem_base);
+    let mut image = vec![0u8; (mem_end - mem_base) as usize];
+    for seg in &info.segments {
+        let start = (seg.vaddr - mem_base) as usize;
+        let bytes = &elf_data[seg.offset as usize..(seg.offset + seg.filesz) as usize];
+        image[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+    (mem_base, image)
+}
+
+/// Run the compiled module's `run` export under `wasmi`, seeding its
+/// linear memory with `image` at `mem_base` first, and return the final
+/// x0-x31 register file read back from the reserved register-file region
+/// at the start of linear memory (see `wasm_builder::DISPATCH_MAP_OFFSET`).
+///
+/// The `run` export's return value is a status code, not the final PC, so
+/// unlike the reference interpreter this comparison covers registers only.
+fn run_compiled(wasm_bytes: &[u8], entry: u64, mem_base: u64, image: &[u8]) -> [u64; 32] {
+    let engine = wasmi::Engine::default();
+    let module = wasmi::Module::new(&engine, wasm_bytes).expect("wasmi failed to parse compiled module");
+    let mut store = wasmi::Store::new(&engine, ());
+
+    let memory_ty = module
+        .imports()
+        .find_map(|i| match i.ty() {
+            wasmi::ExternType::Memory(mt) if i.name() == "memory" => Some(*mt),
+            _ => None,
+        })
+        .expect("compiled module always imports env.memory");
+    let memory = wasmi::Memory::new(&mut store, memory_ty).expect("memory import creation failed");
+
+    // No fixture in this suite issues a syscall; stub the import out as an
+    // immediate halt (next-pc = -1) rather than guessing at real syscall
+    // semantics here.
+    let syscall = wasmi::Func::wrap(&mut store, |_m: i32, _pc: i32| -> i32 { -1 });
+
+    let mut linker = wasmi::Linker::new(&engine);
+    linker.define("env", "memory", memory).unwrap();
+    linker.define("env", "syscall", syscall).unwrap();
+
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .and_then(|pre| pre.start(&mut store))
+        .expect("instantiation failed");
+
+    let end = (mem_base as usize) + image.len();
+    assert!(
+        end <= memory.data(&store).len(),
+        "fixture image ({end} bytes) does not fit the compiled module's declared memory"
+    );
+    memory.data_mut(&mut store)[mem_base as usize..end].copy_from_slice(image);
+
+    let run = instance
+        .get_typed_func::<(i32, i32), i32>(&store, "run")
+        .expect("compiled module always exports `run`");
+    run.call(&mut store, (0, entry as i32)).expect("compiled module trapped");
+
+    let mem = memory.data(&store);
+    let mut regs = [0u64; 32];
+    for (i, reg) in regs.iter_mut().enumerate() {
+        *reg = u64::from_le_bytes(mem[i * 8..i * 8 + 8].try_into().unwrap());
+    }
+    regs
+}
+
+/// Generates one `#[test]` per fixture name that runs both the reference
+/// interpreter and the compiled Wasm module and asserts their final
+/// register state agrees.
+macro_rules! run_test {
+    ($test_name:ident, $fixture:literal) => {
+        #[test]
+        fn $test_name() {
+            let path = fixture_path($fixture);
+            if !path.exists() {
+                eprintln!("Skipping {}: {} not found", stringify!($test_name), path.display());
+                return;
+            }
+
+            let elf_data = std::fs::read(&path).expect("failed to read fixture");
+            let info = rv2wasm::elf::parse(&elf_data).expect("ELF parse failed");
+            let sections = rv2wasm::elf::extract_code_sections(&elf_data, &info).expect("code extraction failed");
+
+            let mut instructions = Vec::new();
+            for section in &sections {
+                instructions.extend(rv2wasm::disasm::disassemble(section, info.xlen).expect("disassembly failed"));
+            }
+            assert!(!instructions.is_empty(), "fixture produced no instructions");
+
+            let program = rv2wasm::interp::lower(&instructions)
+                .expect("reference interpreter has no semantics for an opcode in this fixture");
+            let (mem_base, image) = load_image(&elf_data, &info);
+
+            let mut reference = rv2wasm::interp::Interp::new(info.entry, mem_base, image.clone());
+            reference.run(&program, 100_000).expect("reference interpreter run failed");
+
+            let wasm_bytes = rv2wasm::compile(&elf_data, 0, false, &[], &[]).expect("compilation failed");
+            let mut validator = wasmparser::Validator::new();
+            validator.validate_all(&wasm_bytes).expect("compiled module is invalid Wasm");
+
+            let wasm_regs = run_compiled(&wasm_bytes, info.entry, mem_base, &image);
+            for i in 0..32 {
+                assert_eq!(
+                    reference.regs[i], wasm_regs[i],
+                    "{}: register x{i} diverged: interp=0x{:x} wasm=0x{:x}",
+                    stringify!($test_name), reference.regs[i], wasm_regs[i]
+                );
+            }
+        }
+    };
+}
+
+run_test!(test_conformance_add, "add.elf");
+run_test!(test_conformance_branch, "branch.elf");
+run_test!(test_conformance_loop, "loop.elf");
+// Exercises the RVC-compressed decode path (C.LI, a 2-byte instruction)
+// followed by a standard 4-byte instruction reading its result, so a
+// reference interpreter that advances pc by a hardcoded 4 regardless of
+// the decoded instruction's length would desync and diverge here.
+run_test!(test_conformance_compressed, "compressed.elf");
@@ -24,7 +24,7 @@ fn test_compile_simple_elf() {
     }
 
     let elf_data = std::fs::read(&elf_path).expect("Failed to read test ELF");
-    let wasm_bytes = rv2wasm::compile(&elf_data, 2, false).expect("Compilation failed");
+    let wasm_bytes = rv2wasm::compile(&elf_data, 2, false, &[], &[]).expect("Compilation failed");
 
     // Validate output is valid Wasm
     assert!(wasm_bytes.len() > 8, "Output too small to be valid Wasm");
@@ -46,7 +46,7 @@ fn test_validate_wasm_structure() {
     }
 
     let elf_data = std::fs::read(&elf_path).expect("Failed to read test ELF");
-    let wasm_bytes = rv2wasm::compile(&elf_data, 2, false).expect("Compilation failed");
+    let wasm_bytes = rv2wasm::compile(&elf_data, 2, false, &[], &[]).expect("Compilation failed");
 
     // Use wasmparser to validate the Wasm binary
     let mut validator = wasmparser::Validator::new();
@@ -87,7 +87,7 @@ fn test_disassembly() {
 
     let mut total_instructions = 0;
     for section in &sections {
-        let instructions = rv2wasm::disasm::disassemble(section).expect("Disassembly failed");
+        let instructions = rv2wasm::disasm::disassemble(section, info.xlen).expect("Disassembly failed");
         total_instructions += instructions.len();
     }
 
@@ -108,7 +108,7 @@ fn test_cfg_construction() {
 
     let mut all_instructions = Vec::new();
     for section in &sections {
-        let instructions = rv2wasm::disasm::disassemble(section).expect("Disassembly failed");
+        let instructions = rv2wasm::disasm::disassemble(section, info.xlen).expect("Disassembly failed");
         all_instructions.extend(instructions);
     }
 
@@ -132,11 +132,11 @@ fn test_debug_mode() {
     let elf_data = std::fs::read(&elf_path).expect("Failed to read test ELF");
 
     // Should compile with debug info without errors
-    let wasm_bytes = rv2wasm::compile(&elf_data, 0, true).expect("Debug compilation failed");
+    let wasm_bytes = rv2wasm::compile(&elf_data, 0, true, &[], &[]).expect("Debug compilation failed");
     assert!(wasm_bytes.len() > 8, "Debug output too small");
 
     // Optimized compilation
-    let wasm_opt = rv2wasm::compile(&elf_data, 2, false).expect("Optimized compilation failed");
+    let wasm_opt = rv2wasm::compile(&elf_data, 2, false, &[], &[]).expect("Optimized compilation failed");
 
     // Debug output is typically larger (has comments, but they compile out)
     eprintln!("Debug size: {}, Optimized size: {}", wasm_bytes.len(), wasm_opt.len());
@@ -197,7 +197,7 @@ fn test_dynamic_elf_compilation() {
     }
 
     let elf_data = std::fs::read(&elf_path).expect("Failed to read dynamic ELF");
-    let wasm_bytes = rv2wasm::compile(&elf_data, 2, false).expect("Dynamic ELF compilation failed");
+    let wasm_bytes = rv2wasm::compile(&elf_data, 2, false, &[], &[]).expect("Dynamic ELF compilation failed");
 
     // Validate Wasm output
     assert!(wasm_bytes.len() > 8, "Output too small");
@@ -222,8 +222,8 @@ fn test_dynamic_elf_has_more_blocks() {
     let static_data = std::fs::read(&static_path).unwrap();
     let dynamic_data = std::fs::read(&dynamic_path).unwrap();
 
-    let static_wasm = rv2wasm::compile(&static_data, 2, false).unwrap();
-    let dynamic_wasm = rv2wasm::compile(&dynamic_data, 2, false).unwrap();
+    let static_wasm = rv2wasm::compile(&static_data, 2, false, &[], &[]).unwrap();
+    let dynamic_wasm = rv2wasm::compile(&dynamic_data, 2, false, &[], &[]).unwrap();
 
     // Dynamic binary (with PLT/GOT stubs, libc startup) should produce more code
     assert!(
@@ -301,7 +301,7 @@ fn test_br_table_dispatch_produces_valid_wasm() {
             continue;
         }
         let elf_data = std::fs::read(path).unwrap();
-        let wasm_bytes = rv2wasm::compile(&elf_data, 2, false)
+        let wasm_bytes = rv2wasm::compile(&elf_data, 2, false, &[], &[])
             .unwrap_or_else(|e| panic!("{} binary: br_table compilation failed: {}", name, e));
 
         let mut validator = wasmparser::Validator::new();
@@ -323,7 +323,7 @@ fn test_wasm_has_data_section() {
     }
 
     let elf_data = std::fs::read(&elf_path).unwrap();
-    let wasm_bytes = rv2wasm::compile(&elf_data, 2, false).unwrap();
+    let wasm_bytes = rv2wasm::compile(&elf_data, 2, false, &[], &[]).unwrap();
 
     // Parse the Wasm and look for sections
     let parser = wasmparser::Parser::new(0);